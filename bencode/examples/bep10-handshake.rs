@@ -0,0 +1,86 @@
+//! Parses extension protocol messages (BEP 10) out of peer wire frames. Each frame is a 4-byte
+//! big-endian length, the message id 20, an extended message id, then a bencoded dict. Metadata
+//! pieces (BEP 9) carry their binary payload straight after the dict in the same frame, so the
+//! dict is read with `from_slice_partial` and whatever follows it is the payload.
+//!
+//! Usage: cargo run --example bep10-handshake
+
+extern crate serde_bencode;
+
+use serde_bencode::Value;
+
+const EXTENDED: u8 = 20;
+const HANDSHAKE: u8 = 0;
+
+/// Builds a frame holding an extended message, as a peer would send it.
+fn frame(extended_id: u8, body: &[u8]) -> Vec<u8> {
+    let len = (body.len() + 2) as u32;
+    let mut frame = vec![(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+    frame.push(EXTENDED);
+    frame.push(extended_id);
+    frame.extend_from_slice(body);
+    frame
+}
+
+/// Splits a frame into its extended message id, the bencoded dict and any trailing payload.
+fn parse_frame(frame: &[u8]) -> Result<(u8, Value, &[u8]), String> {
+    if frame.len() < 6 {
+        return Err("frame too short".to_string());
+    }
+    let len = ((frame[0] as usize) << 24) | ((frame[1] as usize) << 16) |
+              ((frame[2] as usize) << 8) | frame[3] as usize;
+    if frame.len() - 4 != len {
+        return Err(format!("frame declares {} bytes but holds {}", len, frame.len() - 4));
+    }
+    if frame[4] != EXTENDED {
+        return Err(format!("not an extended message: id {}", frame[4]));
+    }
+    let (dict, payload) = try!(serde_bencode::from_slice_partial::<Value>(&frame[6..])
+        .map_err(|err| err.context("failed to parse message dict").to_string()));
+    Ok((frame[5], dict, payload))
+}
+
+fn describe(frame: &[u8]) {
+    let (extended_id, dict, payload) = match parse_frame(frame) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            println!("bad frame: {}", err);
+            return;
+        }
+    };
+    if extended_id == HANDSHAKE {
+        println!("handshake");
+        if let Some(Value::Dict(m)) = dict.get_path(&[b"m"]) {
+            for (name, id) in m {
+                println!("  supports {} as message {}",
+                         String::from_utf8_lossy(&name),
+                         id.as_i64().unwrap_or(0));
+            }
+        }
+        if let Some(Value::ByteString(client)) = dict.get_path(&[b"v"]) {
+            println!("  client: {}", String::from_utf8_lossy(&client));
+        }
+        if let Some(size) = dict.get_path(&[b"metadata_size"]).and_then(|n| n.as_i64()) {
+            println!("  metadata size: {}", size);
+        }
+    } else {
+        let piece = dict.get_path(&[b"piece"]).and_then(|n| n.as_i64()).unwrap_or(-1);
+        println!("extended message {}: piece {} with a {}-byte payload",
+                 extended_id,
+                 piece,
+                 payload.len());
+    }
+}
+
+fn main() {
+    let handshake = frame(HANDSHAKE,
+                          b"d1:md11:ut_metadatai3e6:ut_pexi1ee13:metadata_sizei31235e1:v11:Example \
+                            1.0e");
+    let mut data = b"d8:msg_typei1e5:piecei0e10:total_sizei31235ee".to_vec();
+    data.extend_from_slice(&[0xab; 16384]);
+    let metadata_piece = frame(3, &data);
+
+    describe(&handshake);
+    describe(&metadata_piece);
+    describe(&frame(3, b"d8:msg_typei1e5:piece"));
+}
@@ -0,0 +1,49 @@
+//! Parses a `.torrent` file without reading it into memory first, by memory-mapping it and
+//! handing the mapped bytes straight to `from_slice`.
+//!
+//! serde 0.8 has no borrowed deserialization, so the parsed `Torrent` still owns copies of its
+//! strings; mapping the file only saves reading all of it into a buffer first.
+//!
+//! Usage: cargo run --example mmap-parse --features torrent -- path/to/file.torrent
+
+extern crate memmap2;
+extern crate serde_bencode;
+
+use std::env;
+use std::fs::File;
+use std::process;
+
+use memmap2::Mmap;
+use serde_bencode::torrent::Torrent;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: mmap-parse <file.torrent>");
+            process::exit(1);
+        }
+    };
+
+    let file = File::open(&path).expect("Failed to open file");
+    // Safety: the file must not be modified by another process while it is mapped.
+    let mmap = unsafe { Mmap::map(&file).expect("Failed to map file") };
+
+    let torrent = Torrent::from_bytes(&mmap).expect("Failed to parse torrent");
+    println!("name: {}", torrent.info.name);
+    if let Some(announce) = torrent.announce {
+        println!("announce: {}", announce);
+    }
+    match torrent.info.files {
+        Some(files) => {
+            for file in files {
+                let path: Vec<String> = file.path
+                    .iter()
+                    .map(|component| String::from_utf8_lossy(component).into_owned())
+                    .collect();
+                println!("{:>12}  {}", file.length, path.join("/"));
+            }
+        }
+        None => println!("{:>12}  {}", torrent.info.length.unwrap_or(0), torrent.info.name),
+    }
+}
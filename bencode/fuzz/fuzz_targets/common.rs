@@ -0,0 +1,41 @@
+//! Helpers shared by the fuzz targets.
+
+#![allow(dead_code)]
+
+use serde_bencode::event::{Event, EventWriter};
+use serde_bencode::Value;
+
+/// Input is decoded with these limits, so that a deeply nested or huge document is rejected
+/// cleanly rather than exhausting the stack or memory.
+pub const MAX_DEPTH: usize = 64;
+pub const MAX_NODES: usize = 100_000;
+pub const MAX_STRING_LEN: usize = 1 << 20;
+
+/// Encodes a `Value` through an `EventWriter`, which checks that the output is canonical.
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut writer = EventWriter::new(Vec::new());
+    write_events(&mut writer, value);
+    writer.finish().expect("complete document")
+}
+
+fn write_events(writer: &mut EventWriter<Vec<u8>>, value: &Value) {
+    match *value {
+        Value::Int(n) => writer.write(&Event::Integer(n)).unwrap(),
+        Value::ByteString(ref bytes) => writer.write(&Event::ByteString(bytes.clone())).unwrap(),
+        Value::List(ref list) => {
+            writer.write(&Event::ListStart).unwrap();
+            for item in list {
+                write_events(writer, item);
+            }
+            writer.write(&Event::ListEnd).unwrap();
+        }
+        Value::Dict(ref dict) => {
+            writer.write(&Event::DictStart).unwrap();
+            for (key, item) in dict {
+                writer.write(&Event::ByteString(key.clone())).unwrap();
+                write_events(writer, item);
+            }
+            writer.write(&Event::DictEnd).unwrap();
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Decodes arbitrary bytes, and checks that anything accepted re-encodes to a document that
+//! decodes to the same value.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_bencode;
+
+use std::collections::BTreeMap;
+
+use serde_bencode::{value, Value};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    // Typed decoding must fail cleanly on malformed input, never panic
+    let _ = serde_bencode::from_slice::<BTreeMap<String, Vec<i64>>>(data);
+    let _ = serde_bencode::from_slice::<Vec<String>>(data);
+
+    let decoded = match Value::from_slice_limited(data,
+                                                  common::MAX_DEPTH,
+                                                  common::MAX_NODES,
+                                                  common::MAX_STRING_LEN) {
+        Ok(decoded) => decoded,
+        Err(_) => return,
+    };
+    let encoded = common::encode(&decoded);
+    assert_eq!(encoded.len(), decoded.serialized_len());
+    let redecoded = value::from_slice_lenient(&encoded).expect("re-encoded value decodes");
+    assert_eq!(redecoded, decoded);
+});
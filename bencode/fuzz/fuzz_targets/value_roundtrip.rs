@@ -0,0 +1,78 @@
+//! Builds an arbitrary `Value` from the fuzzer's bytes, and checks that it survives being encoded
+//! and decoded again.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_bencode;
+
+use std::collections::BTreeMap;
+
+use serde_bencode::Value;
+
+mod common;
+
+/// Consumes bytes from the front of the input to decide the shape of the value.
+struct Source<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Source<'a> {
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((&b, rest)) => {
+                self.data = rest;
+                b
+            }
+            None => 0,
+        }
+    }
+
+    fn bytes(&mut self) -> Vec<u8> {
+        let len = (self.byte() as usize).min(self.data.len());
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        bytes.to_vec()
+    }
+
+    fn int(&mut self) -> i64 {
+        let mut buf = [0; 8];
+        for b in buf.iter_mut() {
+            *b = self.byte();
+        }
+        i64::from_le_bytes(buf)
+    }
+
+    fn value(&mut self, depth: usize) -> Value {
+        let tag = if depth >= common::MAX_DEPTH { self.byte() % 2 } else { self.byte() % 4 };
+        match tag {
+            0 => Value::Int(self.int()),
+            1 => Value::ByteString(self.bytes()),
+            2 => {
+                let len = self.byte() % 8;
+                Value::List((0..len).map(|_| self.value(depth + 1)).collect())
+            }
+            _ => {
+                let len = self.byte() % 8;
+                let mut dict = BTreeMap::new();
+                for _ in 0..len {
+                    let key = self.bytes();
+                    dict.insert(key, self.value(depth + 1));
+                }
+                Value::Dict(dict)
+            }
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let value = Source { data: data }.value(0);
+    let encoded = common::encode(&value);
+    assert_eq!(encoded.len(), value.serialized_len());
+    let decoded = Value::from_slice_limited(&encoded,
+                                            common::MAX_DEPTH,
+                                            usize::max_value(),
+                                            usize::max_value())
+        .expect("encoded value decodes");
+    assert_eq!(decoded, value);
+});
@@ -0,0 +1,98 @@
+//! Helpers for encoding fixed-size byte arrays, such as a 20-byte info hash or peer ID, as a
+//! bencode byte string of exactly that length rather than as a list of integers.
+//!
+//! Use them with serde's field attributes:
+//!
+//! ```rust,ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Handshake {
+//!     #[serde(serialize_with = "serde_bencode::byte_array::serialize",
+//!             deserialize_with = "serde_bencode::byte_array::deserialize")]
+//!     info_hash: [u8; 20],
+//! }
+//! ```
+//!
+//! Decoding fails if the byte string is not exactly as long as the array.
+
+use serde::bytes::ByteBuf;
+use serde::de::{self, Deserialize};
+use serde::ser;
+
+/// Serializes a byte array as a bencode byte string.
+pub fn serialize<S, const N: usize>(bytes: &[u8; N], serializer: &mut S) -> Result<(), S::Error>
+    where S: ser::Serializer
+{
+    serializer.serialize_bytes(bytes)
+}
+
+/// Deserializes a byte array from a bencode byte string of exactly `N` bytes.
+pub fn deserialize<D, const N: usize>(deserializer: &mut D) -> Result<[u8; N], D::Error>
+    where D: de::Deserializer
+{
+    let buf = try!(ByteBuf::deserialize(deserializer));
+    if buf.len() != N {
+        return Err(de::Error::invalid_value(&format!("expected a byte string of length {}, \
+                                                      found one of length {}",
+                                                     N,
+                                                     buf.len())));
+    }
+    let mut bytes = [0; N];
+    bytes.copy_from_slice(&buf);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use de::Deserializer;
+    use error::{Error, ErrorCode};
+    use read::SliceRead;
+    use ser::Serializer;
+
+    #[test]
+    fn test_round_trip() {
+        let mut hash = [0u8; 20];
+        for (i, b) in hash.iter_mut().enumerate() {
+            *b = 0xff - i as u8;
+        }
+
+        let mut w = Vec::new();
+        serialize(&hash, &mut Serializer::new(&mut w)).expect("Failed to serialize byte array");
+        assert_eq!(&w[..3], b"20:");
+        assert_eq!(&w[3..], &hash[..]);
+
+        let mut de = Deserializer::new(SliceRead::new(&w));
+        let decoded: [u8; 20] = deserialize(&mut de).unwrap();
+        assert_eq!(decoded, hash);
+    }
+
+    #[test]
+    fn test_empty_array() {
+        let mut w = Vec::new();
+        serialize(&[0u8; 0], &mut Serializer::new(&mut w)).unwrap();
+        assert_eq!(w, b"0:");
+
+        let mut de = Deserializer::new(SliceRead::new(&w));
+        let decoded: [u8; 0] = deserialize(&mut de).unwrap();
+        assert_eq!(decoded, []);
+    }
+
+    #[test]
+    fn test_wrong_length() {
+        let mut de = Deserializer::new(SliceRead::new(b"3:abc"));
+        match deserialize::<_, 4>(&mut de) {
+            Err(Error::Syntax(ErrorCode::Custom(msg), _)) => {
+                assert_eq!(msg,
+                           "Invalid value: expected a byte string of length 4, found one of \
+                            length 3")
+            }
+            other => panic!("Expected a length error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_not_a_byte_string() {
+        let mut de = Deserializer::new(SliceRead::new(b"i1e"));
+        assert!(deserialize::<_, 1>(&mut de).is_err());
+    }
+}
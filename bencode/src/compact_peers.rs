@@ -0,0 +1,99 @@
+//! Helpers for the compact peer list format of
+//! [BEP 23](http://bittorrent.org/beps/bep_0023.html), in which a tracker returns its IPv4 peers
+//! as a single byte string of 6 bytes per peer: the 4-byte address followed by the 2-byte port,
+//! both in network byte order.
+//!
+//! Use them with serde's field attributes:
+//!
+//! ```rust,ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct TrackerResponse {
+//!     interval: i64,
+//!     #[serde(serialize_with = "serde_bencode::compact_peers::serialize",
+//!             deserialize_with = "serde_bencode::compact_peers::deserialize")]
+//!     peers: Vec<SocketAddrV4>,
+//! }
+//! ```
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use serde::bytes::ByteBuf;
+use serde::de::{self, Deserialize};
+use serde::ser;
+
+/// The number of bytes each peer takes up.
+const PEER_LEN: usize = 6;
+
+/// Serializes a list of peers as a single compact byte string.
+pub fn serialize<S>(peers: &[SocketAddrV4], serializer: &mut S) -> Result<(), S::Error>
+    where S: ser::Serializer
+{
+    let mut buf = Vec::with_capacity(peers.len() * PEER_LEN);
+    for peer in peers {
+        buf.extend_from_slice(&peer.ip().octets());
+        buf.extend_from_slice(&peer.port().to_be_bytes());
+    }
+    serializer.serialize_bytes(&buf)
+}
+
+/// Deserializes a list of peers from a compact byte string, whose length must be a multiple of 6.
+pub fn deserialize<D>(deserializer: &mut D) -> Result<Vec<SocketAddrV4>, D::Error>
+    where D: de::Deserializer
+{
+    let buf = try!(ByteBuf::deserialize(deserializer));
+    if buf.len() % PEER_LEN != 0 {
+        return Err(de::Error::invalid_value(&format!("expected a multiple of {} bytes for \
+                                                      compact peers, found {}",
+                                                     PEER_LEN,
+                                                     buf.len())));
+    }
+    Ok(buf.chunks(PEER_LEN)
+        .map(|peer| {
+            let ip = Ipv4Addr::new(peer[0], peer[1], peer[2], peer[3]);
+            SocketAddrV4::new(ip, u16::from_be_bytes([peer[4], peer[5]]))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::*;
+    use de::Deserializer;
+    use read::SliceRead;
+    use ser::Serializer;
+
+    #[test]
+    fn test_round_trip() {
+        let peers = vec![SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 6881),
+                         SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 255), 80),
+                         SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), 65535)];
+
+        let mut w = Vec::new();
+        serialize(&peers, &mut Serializer::new(&mut w)).unwrap();
+        let expected: &[u8] = b"18:\x0a\x00\x00\x01\x1a\xe1\
+                                \xc0\xa8\x01\xff\x00\x50\
+                                \x01\x02\x03\x04\xff\xff";
+        assert_eq!(w, expected);
+
+        let mut de = Deserializer::new(SliceRead::new(&w));
+        assert_eq!(deserialize(&mut de).unwrap(), peers);
+    }
+
+    #[test]
+    fn test_no_peers() {
+        let mut w = Vec::new();
+        serialize(&[], &mut Serializer::new(&mut w)).unwrap();
+        assert_eq!(w, b"0:");
+
+        let mut de = Deserializer::new(SliceRead::new(b"0:"));
+        assert_eq!(deserialize(&mut de).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_partial_peer() {
+        let mut de = Deserializer::new(SliceRead::new(b"7:\x0a\x00\x00\x01\x1a\xe1\x00"));
+        assert!(deserialize(&mut de).is_err());
+    }
+}
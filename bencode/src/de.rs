@@ -1,24 +1,195 @@
+use std::ascii;
 use std::io;
+use std::marker::PhantomData;
+use std::str;
 
+use serde::bytes::Bytes;
 use serde::de;
+use serde::de::value::ValueDeserializer;
 
 use super::error::{Error, ErrorCode, Result};
+use super::raw;
 use super::read::{self, Read};
+use super::ser::{Formatter, VariantEncoding};
+
+/// Implements a `Deserializer` method for a primitive integer type, checking that the value read
+/// fits in that type before handing it to the visitor.
+macro_rules! deserialize_int {
+    ($method:ident, $ty:ident, $visit:ident) => {
+        fn $method<V>(&mut self, mut visitor: V) -> Result<V::Value>
+            where V: de::Visitor
+        {
+            const INT_OPEN: u8 = b'i';
+            match try!(self.peek_char()) {
+                Some(INT_OPEN) => {
+                    let start = self.reader.position();
+                    try!(self.next_char());
+                    try!(self.begin_value(false));
+                    let value = try!(self.read_integer());
+                    if value < $ty::min_value() as i128 || value > $ty::max_value() as i128 {
+                        let code = ErrorCode::IntegerOutOfRange {
+                            value: value,
+                            target: stringify!($ty),
+                        };
+                        return Err(Error::Syntax(code, start));
+                    }
+                    visitor.$visit(value as $ty)
+                }
+                _ => self.deserialize(visitor),
+            }
+        }
+    };
+}
+
+/// How deeply lists and dicts may nest unless `Deserializer::max_depth` says otherwise. Each level
+/// of nesting takes a few stack frames, so this keeps crafted input such as a long run of `l`s
+/// from overflowing the stack.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// The longest string a `Deserializer` accepts unless `Deserializer::max_string_len` says
+/// otherwise: 64 MiB, several times the `pieces` string of even a very large torrent. Without a
+/// limit, a length prefix such as `99999999999:` would have the deserializer try to allocate that
+/// much before finding out whether the input holds it.
+pub const DEFAULT_MAX_STRING_LEN: usize = 64 << 20;
 
 pub struct Deserializer<R>
     where R: Read
 {
     reader: R,
+    variant_encoding: VariantEncoding,
+    max_depth: Option<usize>,
+    max_nodes: Option<usize>,
+    max_string_len: Option<usize>,
+    max_length_digits: Option<usize>,
+    legacy_bytes: bool,
+    check_key_order: bool,
+    case_insensitive_fields: bool,
+    deny_unknown_fields: bool,
+    depth: usize,
+    nodes: usize,
 }
 
 impl<R> Deserializer<R>
     where R: Read
 {
     pub fn new(reader: R) -> Self {
-        Deserializer { reader: reader }
+        Deserializer {
+            reader: reader,
+            variant_encoding: VariantEncoding::default(),
+            max_depth: Some(DEFAULT_MAX_DEPTH),
+            max_nodes: None,
+            max_string_len: Some(DEFAULT_MAX_STRING_LEN),
+            max_length_digits: None,
+            legacy_bytes: false,
+            check_key_order: false,
+            case_insensitive_fields: false,
+            deny_unknown_fields: false,
+            depth: 0,
+            nodes: 0,
+        }
+    }
+
+    /// Sets how enum variants holding data are expected to be encoded. This must match the
+    /// encoding the data was serialized with. The default is `VariantEncoding::Dict`.
+    pub fn variant_encoding(mut self, encoding: VariantEncoding) -> Self {
+        self.variant_encoding = encoding;
+        self
+    }
+
+    /// Creates a deserializer that allows lists and dicts to nest at most `max_depth` deep.
+    pub fn with_max_depth(reader: R, max_depth: usize) -> Self {
+        Deserializer::new(reader).max_depth(max_depth)
+    }
+
+    /// Limits how deeply lists and dicts may be nested. A top-level list or dict is at depth 1.
+    /// Exceeding the limit fails with `ErrorCode::DepthLimitExceeded`. The default is
+    /// `DEFAULT_MAX_DEPTH`.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Limits the total number of values read, counting every integer, string, list and dict,
+    /// including dict keys. Exceeding the limit fails with `ErrorCode::NodeLimitExceeded`.
+    pub fn max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    /// Limits the declared length of any one string. The length is checked before anything is
+    /// allocated for the string. Exceeding the limit fails with `ErrorCode::StringTooLong`. The
+    /// default is `DEFAULT_MAX_STRING_LEN`; pass `usize::MAX` to lift the limit.
+    pub fn max_string_len(mut self, max_string_len: usize) -> Self {
+        self.max_string_len = Some(max_string_len);
+        self
+    }
+
+    /// Limits the number of digits in the length prefix of a string, so a long run of digits is
+    /// rejected as soon as the limit is passed rather than read to the end. Exceeding the limit
+    /// fails with `ErrorCode::LengthPrefixTooLong`. Leading zeros are always rejected, whatever
+    /// the limit.
+    pub fn max_length_digits(mut self, max_length_digits: usize) -> Self {
+        self.max_length_digits = Some(max_length_digits);
+        self
+    }
+
+    /// When set, byte buffers may also be read from a list of integers, as written by versions of
+    /// this crate that serialized bytes as a list rather than a byte string. Each integer must
+    /// fit in a `u8`.
+    pub fn legacy_bytes(mut self, legacy_bytes: bool) -> Self {
+        self.legacy_bytes = legacy_bytes;
+        self
+    }
+
+    /// When set, dict keys must be unique and in sorted order, as the bencode spec requires. A key
+    /// that repeats or sorts before the previous key fails with `ErrorCode::DuplicateKey` or
+    /// `ErrorCode::UnsortedKeys`. Off by default, so dicts written by lax encoders still decode.
+    pub fn check_key_order(mut self, check_key_order: bool) -> Self {
+        self.check_key_order = check_key_order;
+        self
+    }
+
+    /// When set, the keys of a struct's dict are matched against its field names ignoring ASCII
+    /// case, so `Port` or `PORT` fills a `port` field. A key matching a field exactly still wins
+    /// over one that only matches ignoring case. Maps and enum variants are not affected.
+    pub fn case_insensitive_fields(mut self, case_insensitive_fields: bool) -> Self {
+        self.case_insensitive_fields = case_insensitive_fields;
+        self
+    }
+
+    /// When set, a key in a struct's dict that names none of its fields fails with
+    /// `ErrorCode::UnknownField`, pointing at the key, instead of being skipped. This applies to
+    /// every struct, whether or not its `Deserialize` impl denies unknown fields itself.
+    pub fn deny_unknown_fields(mut self, deny_unknown_fields: bool) -> Self {
+        self.deny_unknown_fields = deny_unknown_fields;
+        self
+    }
+
+    /// Accounts for the start of a value against the node limit, and against the depth limit if
+    /// it is a list or dict. Every container started must be closed with `end_container`.
+    pub(crate) fn begin_value(&mut self, container: bool) -> Result<()> {
+        self.nodes += 1;
+        if let Some(max) = self.max_nodes {
+            if self.nodes > max {
+                return Err(self.syntax_error(ErrorCode::NodeLimitExceeded(max)));
+            }
+        }
+        if container {
+            self.depth += 1;
+            if let Some(max) = self.max_depth {
+                if self.depth > max {
+                    return Err(self.syntax_error(ErrorCode::DepthLimitExceeded(max)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn end_container(&mut self) {
+        self.depth -= 1;
     }
 
-    fn next_char(&mut self) -> Result<u8> {
+    pub(crate) fn next_char(&mut self) -> Result<u8> {
         match self.reader.next_char() {
             Some(Ok(t)) => Ok(t),
             Some(err_res) => err_res.map_err(From::from),
@@ -26,8 +197,32 @@ impl<R> Deserializer<R>
         }
     }
 
-    fn peek_char(&self) -> Option<u8> {
-        self.reader.peek_char()
+    /// The number of bytes consumed so far.
+    pub(crate) fn position(&self) -> usize {
+        self.reader.position()
+    }
+
+    /// Returns the next byte without consuming it, or `None` at the end of the input.
+    pub(crate) fn peek_char(&mut self) -> Result<Option<u8>> {
+        match self.reader.peek_char() {
+            Some(Ok(ch)) => Ok(Some(ch)),
+            Some(Err(err)) => Err(err),
+            None => Ok(None),
+        }
+    }
+
+    /// Deserializes the next value, pointing any error raised without a position of its own at
+    /// the start of that value. Errors created through `de::Error`, such as a `Deserialize` impl
+    /// rejecting what it read, always have position 0, and no real error in a value that starts
+    /// later can be at 0.
+    fn deserialize_located<T>(&mut self) -> Result<T>
+        where T: de::Deserialize
+    {
+        let start = self.reader.position();
+        de::Deserialize::deserialize(self).map_err(|err| match err {
+            Error::Syntax(code, 0) => Error::Syntax(code, start),
+            err => err,
+        })
     }
 
     fn parse_next<V>(&mut self, mut visitor: V) -> Result<V::Value>
@@ -38,6 +233,7 @@ impl<R> Deserializer<R>
         const INT_OPEN: u8 = b'i';
 
         let ch = try!(self.next_char());
+        try!(self.begin_value(ch == DICT_OPEN || ch == LIST_OPEN));
         match ch {
             DICT_OPEN => visitor.visit_map(MapVisitor::new(self)),
             LIST_OPEN => visitor.visit_seq(SeqVisitor::new(self)),
@@ -47,66 +243,352 @@ impl<R> Deserializer<R>
         }
     }
 
+    /// Hands a string to the visitor as a `str` if it is valid UTF-8, and as its raw bytes
+    /// otherwise, so that binary strings such as a torrent's `pieces` can be read by anything
+    /// accepting bytes. When the reader can lend out its input, the string is handed over without
+    /// being copied.
     fn parse_string<V>(&mut self, init_len_digit: u8, mut visitor: V) -> Result<V::Value>
         where V: de::Visitor
     {
+        let len = try!(self.read_string_len(init_len_digit));
+        match self.reader.read_slice(len) {
+            Some(Ok(bytes)) => {
+                if bytes.len() < len {
+                    return Err(self.unexpected_eof());
+                }
+                match str::from_utf8(bytes) {
+                    Ok(s) => visitor.visit_str(s),
+                    Err(_) => visitor.visit_bytes(bytes),
+                }
+            }
+            Some(Err(err)) => Err(err),
+            None => {
+                let buf = try!(self.read_string_bytes(len));
+                match String::from_utf8(buf) {
+                    Ok(s) => visitor.visit_string(s),
+                    Err(err) => visitor.visit_byte_buf(err.into_bytes()),
+                }
+            }
+        }
+    }
+
+    /// Reads the remainder of a string whose first length digit has already been consumed,
+    /// returning its raw bytes.
+    pub(crate) fn read_string(&mut self, init_len_digit: u8) -> Result<Vec<u8>> {
+        let len = try!(self.read_string_len(init_len_digit));
+        self.read_string_bytes(len)
+    }
+
+    /// Reads the `len` bytes of a string following its length prefix.
+    fn read_string_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        match self.reader.read_slice(len) {
+            Some(Ok(bytes)) => {
+                if bytes.len() < len {
+                    return Err(self.unexpected_eof());
+                }
+                return Ok(bytes.to_vec());
+            }
+            Some(Err(err)) => return Err(err),
+            None => {}
+        }
+        // The whole string is allocated up front, so reading it never reallocates
+        let mut buf: Vec<u8> = vec![];
+        if buf.try_reserve_exact(len).is_err() {
+            return Err(self.syntax_error(ErrorCode::AllocationFailed(len)));
+        }
+        for _ in 0..len {
+            let ch = try!(self.next_char());
+            buf.push(ch);
+        }
+        Ok(buf)
+    }
+
+    /// Reads the length prefix of a string, up to and including the `:`. Any byte that is neither
+    /// a digit nor the `:` is reported as an `InvalidLengthPrefix` at its position. As with
+    /// integers, a length may only start with `0` if it is `0` itself, so `05:hello` is
+    /// rejected at the `5`.
+    fn read_string_len(&mut self, init_len_digit: u8) -> Result<usize> {
         const COLON: u8 = b':';
+        // The first digit has already been read, so a limit of zero is checked here
+        if self.max_length_digits == Some(0) {
+            let pos = self.reader.position().saturating_sub(1);
+            return Err(Error::Syntax(ErrorCode::LengthPrefixTooLong(0), pos));
+        }
         if init_len_digit == b'0' {
             let colon = try!(self.next_char());
             if colon != COLON {
-                return Err(self.unexpected_token(colon));
+                return Err(self.invalid_length_prefix());
             }
-            return visitor.visit_str("");
+            return Ok(0);
         }
 
-        let len = try!(self.read_digits_to(COLON, Some(init_len_digit))) as usize;
-        let mut buf: Vec<u8> = vec![];
-        for _ in 0..len {
+        let max_digits = self.max_length_digits;
+        let len = match self.read_digits_to(COLON,
+                                            Some(init_len_digit),
+                                            max_digits,
+                                            u64::max_value() as u128) {
+            Ok(len) => len as usize,
+            Err(Error::Syntax(ErrorCode::UnexpectedToken(_), _)) => {
+                return Err(self.invalid_length_prefix())
+            }
+            Err(err) => return Err(err),
+        };
+        if let Some(max) = self.max_string_len {
+            if len > max {
+                return Err(self.syntax_error(ErrorCode::StringTooLong(len)));
+            }
+        }
+        Ok(len)
+    }
+
+    /// Skips over the remainder of a string without reading its contents.
+    fn skip_string(&mut self, init_len_digit: u8) -> Result<()> {
+        let len = try!(self.read_string_len(init_len_digit));
+        if try!(self.reader.skip(len)) < len {
+            return Err(self.unexpected_eof());
+        }
+        Ok(())
+    }
+
+    /// Reads the next complete value, returning its encoded bytes. Readers holding the whole
+    /// input lend out the span the value occupied once it has been checked. Otherwise, since
+    /// anything this deserializer accepts has exactly one encoding, writing the tokens back out
+    /// as they are read reproduces the input byte for byte. Either way, the input is held to the
+    /// same rules as when it is deserialized into a value.
+    fn read_raw(&mut self) -> Result<Vec<u8>> {
+        const DICT_OPEN: u8 = b'd';
+        const LIST_OPEN: u8 = b'l';
+        const INT_OPEN: u8 = b'i';
+        const END: u8 = b'e';
+
+        if self.reader.source().is_some() {
+            let start = self.reader.position();
+            try!(<de::impls::IgnoredAny as de::Deserialize>::deserialize(self));
+            let end = self.reader.position();
+            return Ok(self.reader.source().map_or_else(Vec::new, |s| s[start..end].to_vec()));
+        }
+
+        // One entry per open list or dict. For a dict, whether a key is expected next and the
+        // last key read, so that dicts are checked just as `MapVisitor` checks them.
+        struct Frame {
+            dict: bool,
+            expecting_key: bool,
+            prev_key: Option<Vec<u8>>,
+        }
+
+        let formatter = Formatter::default();
+        let mut raw = vec![];
+        let mut stack: Vec<Frame> = vec![];
+        loop {
+            let start = self.reader.position();
             let ch = try!(self.next_char());
-            buf.push(ch);
+            let (in_dict, at_key) = match stack.last() {
+                Some(frame) => (frame.dict, frame.dict && frame.expecting_key),
+                None => (false, false),
+            };
+            match ch {
+                END if !stack.is_empty() => {
+                    if in_dict && !at_key {
+                        return Err(self.unexpected_token(ch));
+                    }
+                    stack.pop();
+                    self.end_container();
+                    raw.push(ch);
+                }
+                b'0'...b'9' => {
+                    try!(self.begin_value(false));
+                    let buf = try!(self.read_string(ch));
+                    if at_key {
+                        let check = self.check_key_order;
+                        if let Some(frame) = stack.last_mut() {
+                            if let Some(ref prev) = frame.prev_key {
+                                if check && buf == *prev {
+                                    return Err(Error::Syntax(ErrorCode::DuplicateKey, start));
+                                }
+                                if check && buf < *prev {
+                                    return Err(Error::Syntax(ErrorCode::UnsortedKeys, start));
+                                }
+                            }
+                            frame.prev_key = Some(buf.clone());
+                        }
+                    }
+                    try!(formatter.bytes(&mut raw, &buf));
+                }
+                _ if at_key => return Err(self.unexpected_token(ch)),
+                DICT_OPEN | LIST_OPEN => {
+                    try!(self.begin_value(true));
+                    raw.push(ch);
+                    stack.push(Frame {
+                        dict: ch == DICT_OPEN,
+                        expecting_key: true,
+                        prev_key: None,
+                    });
+                    continue;
+                }
+                INT_OPEN => {
+                    try!(self.begin_value(false));
+                    let num = try!(self.read_integer());
+                    raw.extend_from_slice(format!("i{}e", num).as_bytes());
+                }
+                _ => return Err(self.unexpected_token(ch)),
+            }
+            // A key, or a complete value, has been read
+            match stack.last_mut() {
+                Some(frame) => {
+                    if frame.dict {
+                        frame.expecting_key = !frame.expecting_key;
+                    }
+                }
+                None => return Ok(raw),
+            }
         }
-        let s = try!(String::from_utf8(buf));
-        visitor.visit_string(s)
     }
 
     fn parse_int<V>(&mut self, mut visitor: V) -> Result<V::Value>
         where V: de::Visitor
     {
+        let num = try!(self.read_integer());
+        if num >= i64::min_value() as i128 && num <= i64::max_value() as i128 {
+            visitor.visit_i64(num as i64)
+        } else if num >= 0 && num <= u64::max_value() as i128 {
+            visitor.visit_u64(num as u64)
+        } else {
+            Err(self.syntax_error(ErrorCode::UnrepresentableInteger(num)))
+        }
+    }
+
+    /// Reads the remainder of an integer whose leading `i` has already been consumed.
+    pub(crate) fn read_int(&mut self) -> Result<i64> {
+        let num = try!(self.read_integer());
+        if num < i64::min_value() as i128 || num > i64::max_value() as i128 {
+            return Err(self.syntax_error(ErrorCode::UnrepresentableInteger(num)));
+        }
+        Ok(num as i64)
+    }
+
+    /// Like `read_int`, but accepts any value whose magnitude fits in a `u64`, so that both
+    /// `i64` and `u64` values can be read.
+    fn read_integer(&mut self) -> Result<i128> {
+        let max = u64::max_value() as u128;
+        let (negative, magnitude) = try!(self.read_signed_digits(max, max));
+        let value = magnitude as i128;
+        Ok(if negative { -value } else { value })
+    }
+
+    /// Reads the remainder of an integer as a sign and a magnitude, failing with
+    /// `IntegerOverflow` if the magnitude is greater than `max_positive` or, for a negative
+    /// value, `max_negative`.
+    fn read_signed_digits(&mut self,
+                          max_positive: u128,
+                          max_negative: u128)
+                          -> Result<(bool, u128)> {
         const END: u8 = b'e';
 
         let ch = try!(self.next_char());
-        let sign = if ch == b'-' { -1 } else { 1 };
-        let initnum = if ch == b'-' {
+        let negative = ch == b'-';
+        let initnum = if negative {
             try!(self.next_char())
         } else {
             ch
         };
-        let num: i64 = try!(match initnum {
+        match initnum {
             b'0' => {
-                if sign == -1 {
+                if negative {
                     return Err(self.unexpected_token(initnum));
                 }
                 let end = try!(self.next_char());
                 if end != END {
                     return Err(self.unexpected_token(end));
                 }
-                Ok(0)
+                Ok((false, 0))
             }
-            END => Err(self.unexpected_token(END)),
-            _ => self.read_digits_to(END, Some(initnum)).map(|n| n * sign),
-        });
+            b'1'...b'9' => {
+                let max = if negative { max_negative } else { max_positive };
+                let magnitude = try!(self.read_digits_to(END, Some(initnum), None, max));
+                Ok((negative, magnitude))
+            }
+            b'+' if !negative => {
+                let pos = self.reader.position().saturating_sub(1);
+                Err(Error::Syntax(ErrorCode::PlusSign, pos))
+            }
+            _ => Err(self.unexpected_token(initnum)),
+        }
+    }
+
+    /// Reads an integer into an `i128`, for values beyond the range of `i64` and `u64`. serde
+    /// 0.8 has no 128-bit integer types, so this is called on the deserializer directly rather
+    /// than through `Deserialize`. A value that does not fit fails with `IntegerOverflow`,
+    /// pointed at its first digit.
+    pub fn deserialize_i128(&mut self) -> Result<i128> {
+        try!(self.begin_int());
+        let max = i128::max_value() as u128;
+        let (negative, magnitude) = try!(self.read_signed_digits(max, max + 1));
+        if negative {
+            Ok((magnitude as i128).wrapping_neg())
+        } else {
+            Ok(magnitude as i128)
+        }
+    }
+
+    /// Reads an integer into a `u128`. See `deserialize_i128`. A negative value fails with
+    /// `IntegerOutOfRange`, pointed at the `i` like the other integer types.
+    pub fn deserialize_u128(&mut self) -> Result<u128> {
+        let start = self.reader.position();
+        try!(self.begin_int());
+        let (negative, magnitude) = try!(self.read_signed_digits(u128::max_value(), 1 << 127));
+        if !negative {
+            return Ok(magnitude);
+        }
+        let code = ErrorCode::IntegerOutOfRange {
+            value: (magnitude as i128).wrapping_neg(),
+            target: "u128",
+        };
+        Err(Error::Syntax(code, start))
+    }
 
-        visitor.visit_i64(num)
+    /// Consumes the `i` opening an integer, failing if the next value is not an integer.
+    fn begin_int(&mut self) -> Result<()> {
+        const INT_OPEN: u8 = b'i';
+        match try!(self.peek_char()) {
+            Some(INT_OPEN) => {}
+            Some(ch) => return Err(self.invalid_peeked_type(ch)),
+            None => return Err(self.unexpected_eof()),
+        }
+        try!(self.next_char());
+        self.begin_value(false)
     }
 
-    fn read_digits_to(&mut self, delim: u8, init_digit: Option<u8>) -> Result<i64> {
-        const DIGIT_ZERO: i64 = 0x30;
+    /// Reads decimal digits up to `delim`, failing with `IntegerOverflow`, pointed at the first
+    /// digit, if they add up to more than `max`.
+    fn read_digits_to(&mut self,
+                      delim: u8,
+                      init_digit: Option<u8>,
+                      max_digits: Option<usize>,
+                      max: u128)
+                      -> Result<u128> {
+        const DIGIT_ZERO: u128 = 0x30;
+        let start = match init_digit {
+            Some(_) => self.reader.position().saturating_sub(1),
+            None => self.reader.position(),
+        };
         let mut ch = try!(self.next_char());
-        let mut acc: i64 = init_digit.map(|ch| (ch as i64) - DIGIT_ZERO).unwrap_or_default();
+        let mut acc: u128 = init_digit.map(|ch| (ch as u128) - DIGIT_ZERO).unwrap_or_default();
+        let mut digits = if init_digit.is_some() { 1 } else { 0 };
         while ch != delim {
             match ch {
                 b'0'...b'9' => {
-                    acc = 10 * acc + ((ch as i64) - DIGIT_ZERO);
+                    digits += 1;
+                    if let Some(max) = max_digits {
+                        if digits > max {
+                            let pos = self.reader.position().saturating_sub(1);
+                            return Err(Error::Syntax(ErrorCode::LengthPrefixTooLong(max), pos));
+                        }
+                    }
+                    acc = match acc.checked_mul(10)
+                        .and_then(|acc| acc.checked_add((ch as u128) - DIGIT_ZERO)) {
+                        Some(acc) if acc <= max => acc,
+                        _ => return Err(Error::Syntax(ErrorCode::IntegerOverflow, start)),
+                    };
                 }
                 _ => {
                     return Err(self.unexpected_token(ch));
@@ -118,28 +600,104 @@ impl<R> Deserializer<R>
         Ok(acc)
     }
 
-    fn end(&self) -> Result<()> {
-        const END: u8 = b'e';
-        match self.peek_char() {
-            Some(END) | None => Ok(()),
-            _ => Err(self.syntax_error(ErrorCode::UnexpectedTrailingChars)),
+    /// Checks that the next value is a list or dict opened by `open`, without consuming anything.
+    /// Any other value is reported as being of the wrong type.
+    pub(crate) fn expect_container(&mut self, open: u8) -> Result<()> {
+        match try!(self.peek_char()) {
+            Some(ch) if ch == open => Ok(()),
+            Some(ch) => Err(self.invalid_peeked_type(ch)),
+            None => Err(self.unexpected_eof()),
+        }
+    }
+
+    /// Reports the value starting with the peeked byte `ch` as being of the wrong type.
+    fn invalid_peeked_type(&mut self, ch: u8) -> Error {
+        let found = match ch {
+            b'd' => de::Type::Map,
+            b'l' => de::Type::Seq,
+            b'i' => de::Type::I64,
+            b'0'...b'9' => de::Type::Bytes,
+            _ => return self.unexpected_peeked_token(ch),
+        };
+        self.syntax_error(ErrorCode::InvalidType(found))
+    }
+
+    pub(crate) fn end(&mut self) -> Result<()> {
+        match try!(self.peek_char()) {
+            None => Ok(()),
+            Some(_) => Err(self.syntax_error(ErrorCode::UnexpectedTrailingChars)),
         }
     }
 
-    fn unexpected_token(&self, ch: u8) -> Error {
-        let s = String::from_utf8(vec![ch]).expect("Non-utf8 string encountered!");
-        self.syntax_error(ErrorCode::UnexpectedToken(s))
+    /// Reports the most recently consumed byte as unexpected, pointing at its position.
+    pub(crate) fn unexpected_token(&mut self, ch: u8) -> Error {
+        let pos = self.reader.position().saturating_sub(1);
+        if self.reader.position() == 1 {
+            // Some tools prepend a UTF-8 byte order mark (EF BB BF) or whitespace to bencode
+            let found = match ch {
+                0xef if self.rest_of_bom() => Some("a byte order mark"),
+                b' ' | b'\t' | b'\r' | b'\n' => Some("whitespace"),
+                _ => None,
+            };
+            if let Some(found) = found {
+                return Error::Syntax(ErrorCode::LeadingGarbage(found), pos);
+            }
+        }
+        // The byte need not be valid UTF-8 on its own, so anything unprintable is escaped
+        let s = ascii::escape_default(ch).map(|b| b as char).collect();
+        Error::Syntax(ErrorCode::UnexpectedToken(s), pos)
+    }
+
+    /// Consumes the two bytes following a leading `EF`, returning whether they complete a byte
+    /// order mark. Only called while reporting an error, so the bytes are not put back.
+    fn rest_of_bom(&mut self) -> bool {
+        for &expected in &[0xbb, 0xbf] {
+            match self.reader.next_char() {
+                Some(Ok(ch)) if ch == expected => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Reports the most recently consumed byte as not belonging in a string's length prefix.
+    fn invalid_length_prefix(&self) -> Error {
+        let pos = self.reader.position().saturating_sub(1);
+        Error::Syntax(ErrorCode::InvalidLengthPrefix, pos)
     }
 
-    fn unexpected_eof(&self) -> Error {
+    /// Like `unexpected_token`, but for a byte that has only been peeked at.
+    fn unexpected_peeked_token(&mut self, ch: u8) -> Error {
+        let _ = self.reader.next_char();
+        self.unexpected_token(ch)
+    }
+
+    pub(crate) fn unexpected_eof(&self) -> Error {
         self.syntax_error(ErrorCode::UnexpectedEOF)
     }
 
-    fn syntax_error(&self, code: ErrorCode) -> Error {
+    pub(crate) fn syntax_error(&self, code: ErrorCode) -> Error {
         Error::Syntax(code, self.reader.position())
     }
 }
 
+impl<R> Deserializer<read::IoRead<R>>
+    where R: io::BufRead
+{
+    /// Creates a deserializer that reads from a long-lived stream, such as a socket carrying
+    /// several messages back to back. Each value read consumes exactly its own bytes, so the
+    /// following value can be read with the same deserializer, or whatever comes next taken back
+    /// out of the stream with `into_inner`.
+    pub fn from_reader(reader: R) -> Self {
+        Deserializer::new(read::IoRead::new(reader))
+    }
+
+    /// Returns the underlying stream, positioned just after the last value read.
+    pub fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+}
+
 impl<R> de::Deserializer for Deserializer<R>
     where R: Read
 {
@@ -152,127 +710,670 @@ impl<R> de::Deserializer for Deserializer<R>
         self.parse_next(visitor)
     }
 
-    forward_to_deserialize! {
-        bool usize u8 u16 u32 u64 isize i8 i16 i32 i64 f32 f64 char str string unit option
-        seq seq_fixed_size bytes map unit_struct newtype_struct tuple_struct struct struct_field
-        tuple enum ignored_any
-    }
-}
-
-struct MapVisitor<'a, R: Read + 'a> {
-    de: &'a mut Deserializer<R>,
-}
+    /// `()` is encoded as an empty dict, `de`.
+    fn deserialize_unit<V>(&mut self, mut visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        const DICT_OPEN: u8 = b'd';
+        const END: u8 = b'e';
 
-impl<'a, R: Read + 'a> MapVisitor<'a, R> {
-    fn new(de: &'a mut Deserializer<R>) -> Self {
-        MapVisitor { de: de }
+        match try!(self.next_char()) {
+            DICT_OPEN => try!(self.begin_value(true)),
+            ch => return Err(self.unexpected_token(ch)),
+        }
+        match try!(self.next_char()) {
+            END => {
+                self.end_container();
+                visitor.visit_unit()
+            }
+            ch => Err(self.unexpected_token(ch)),
+        }
     }
-}
 
-impl<'a, R: Read + 'a> de::MapVisitor for MapVisitor<'a, R> {
-    type Error = Error;
+    /// `None` has no encoding of its own: it is written by leaving a dict entry out, and an absent
+    /// entry is read back as `None` by `MapVisitor::missing_field`. Any value that is present is
+    /// therefore `Some`, and only the end of the input reads as `None`.
+    fn deserialize_option<V>(&mut self, mut visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        match try!(self.peek_char()) {
+            Some(_) => visitor.visit_some(self),
+            None => visitor.visit_none(),
+        }
+    }
 
-    fn visit_key<K>(&mut self) -> Result<Option<K>>
-        where K: de::Deserialize
+    /// Byte strings are handed to the visitor as raw bytes, without checking that they are valid
+    /// UTF-8. Any other value is of the wrong type, including a list of integers unless
+    /// `legacy_bytes` is set.
+    fn deserialize_bytes<V>(&mut self, mut visitor: V) -> Result<V::Value>
+        where V: de::Visitor
     {
-        const END: u8 = b'e';
-        match self.de.peek_char() {
-            Some(END) => Ok(None),
-            Some(ch) => {
-                match ch {
-                    b'0'...b'9' => Ok(Some(try!(de::Deserialize::deserialize(self.de)))),
-                    _ => Err(self.de.unexpected_token(ch)),
-                }
+        match try!(self.peek_char()) {
+            Some(ch @ b'0'...b'9') => {
+                try!(self.next_char());
+                try!(self.begin_value(false));
+                let buf = try!(self.read_string(ch));
+                visitor.visit_byte_buf(buf)
             }
-            _ => Err(self.de.unexpected_eof()),
+            Some(b'l') if self.legacy_bytes => self.deserialize(visitor),
+            Some(ch) => Err(self.invalid_peeked_type(ch)),
+            None => Err(self.unexpected_eof()),
         }
     }
 
-    fn visit_value<V>(&mut self) -> Result<V>
-        where V: de::Deserialize
+    /// Bencode has no boolean type, so a `bool` is read from the integers `i0e` and `i1e`, as
+    /// written by a serializer with `bool_as_int` set. Any other integer is out of range, and
+    /// strings such as `4:true` are of the wrong type.
+    fn deserialize_bool<V>(&mut self, mut visitor: V) -> Result<V::Value>
+        where V: de::Visitor
     {
-        Ok(try!(de::Deserialize::deserialize(self.de)))
+        match try!(self.peek_char()) {
+            Some(b'i') => {
+                let start = self.reader.position();
+                try!(self.next_char());
+                try!(self.begin_value(false));
+                match try!(self.read_integer()) {
+                    0 => visitor.visit_bool(false),
+                    1 => visitor.visit_bool(true),
+                    value => {
+                        let code = ErrorCode::IntegerOutOfRange {
+                            value: value,
+                            target: "bool",
+                        };
+                        Err(Error::Syntax(code, start))
+                    }
+                }
+            }
+            Some(ch) => Err(self.invalid_peeked_type(ch)),
+            None => Err(self.unexpected_eof()),
+        }
     }
 
-    fn end(&mut self) -> Result<()> {
-        const END: u8 = b'e';
-        match try!(self.de.next_char()) {
-            END => Ok(()),
-            ch => Err(self.de.unexpected_token(ch)),
+    /// Unlike other targets, which are given the raw bytes of a string that isn't valid UTF-8,
+    /// a `String` fails with `Error::Utf8`.
+    fn deserialize_string<V>(&mut self, mut visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        match try!(self.peek_char()) {
+            Some(ch @ b'0'...b'9') => {
+                try!(self.next_char());
+                try!(self.begin_value(false));
+                let buf = try!(self.read_string(ch));
+                visitor.visit_string(try!(String::from_utf8(buf)))
+            }
+            _ => self.deserialize(visitor),
         }
     }
 
-    fn missing_field<V>(&mut self, field: &'static str) -> Result<V>
-        where V: de::Deserialize
+    fn deserialize_str<V>(&mut self, visitor: V) -> Result<V::Value>
+        where V: de::Visitor
     {
-        use std;
-
-        struct MissingFieldDeserializer(&'static str);
-
-        impl de::Deserializer for MissingFieldDeserializer {
-            type Error = de::value::Error;
+        self.deserialize_string(visitor)
+    }
 
-            fn deserialize<V>(&mut self, _visitor: V) -> std::result::Result<V::Value, Self::Error>
-                where V: de::Visitor
-            {
-                let &mut MissingFieldDeserializer(field) = self;
-                Err(de::value::Error::MissingField(field))
+    /// Strings are skipped without being read, which for a `SliceRead` takes constant time
+    /// regardless of their length. Lists and dicts are still walked, since their ends can only be
+    /// found by parsing their contents.
+    fn deserialize_ignored_any<V>(&mut self, mut visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        match try!(self.peek_char()) {
+            Some(ch @ b'0'...b'9') => {
+                try!(self.next_char());
+                try!(self.begin_value(false));
+                try!(self.skip_string(ch));
+                visitor.visit_unit()
             }
+            _ => self.deserialize(visitor),
+        }
+    }
 
-            fn deserialize_option<V>(&mut self,
+    /// Newtype structs are encoded as the value they wrap. The `raw` helpers go through here too,
+    /// under a reserved name, to capture a value's encoded bytes.
+    fn deserialize_newtype_struct<V>(&mut self,
+                                     name: &'static str,
                                      mut visitor: V)
-                                     -> std::result::Result<V::Value, Self::Error>
-                where V: de::Visitor
-            {
-                visitor.visit_none()
-            }
-
-            forward_to_deserialize! {
-                bool usize u8 u16 u32 u64 isize i8 i16 i32 i64 f32 f64 char str
-                string unit seq seq_fixed_size bytes map unit_struct
-                newtype_struct tuple_struct struct struct_field tuple enum
-                ignored_any
-            }
+                                     -> Result<V::Value>
+        where V: de::Visitor
+    {
+        if name == raw::TOKEN {
+            let raw = try!(self.read_raw());
+            return visitor.visit_byte_buf(raw);
         }
-
-        let mut de = MissingFieldDeserializer(field);
-        Ok(try!(de::Deserialize::deserialize(&mut de)))
+        visitor.visit_newtype_struct(self)
     }
-}
-
-struct SeqVisitor<'a, R: Read + 'a> {
-    de: &'a mut Deserializer<R>,
-}
 
-impl<'a, R: Read + 'a> SeqVisitor<'a, R> {
-    fn new(de: &'a mut Deserializer<R>) -> Self {
-        SeqVisitor { de: de }
+    /// Unit structs are encoded the same way as `()`.
+    fn deserialize_unit_struct<V>(&mut self, _name: &'static str, visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        self.deserialize_unit(visitor)
     }
-}
-
-impl<'a, R: Read + 'a> de::SeqVisitor for SeqVisitor<'a, R> {
-    type Error = Error;
 
-    fn visit<V>(&mut self) -> Result<Option<V>>
-        where V: de::Deserialize
+    /// Unit variants are encoded as a bare string holding the variant name, while all other
+    /// variants are encoded as a single-key dict mapping the variant name to its value, or as a
+    /// list of the two, depending on the `VariantEncoding`.
+    fn deserialize_enum<V>(&mut self,
+                           _name: &str,
+                           _variants: &'static [&'static str],
+                           mut visitor: V)
+                           -> Result<V::Value>
+        where V: de::EnumVisitor
     {
         const END: u8 = b'e';
-        match self.de.peek_char() {
-            Some(END) => Ok(None),
-            Some(_) => Ok(Some(try!(de::Deserialize::deserialize(self.de)))),
-            None => Err(self.de.unexpected_eof()),
-        }
-    }
+        let variant_open = match self.variant_encoding {
+            VariantEncoding::Dict => b'd',
+            VariantEncoding::List => b'l',
+        };
 
-    fn end(&mut self) -> Result<()> {
-        const END: u8 = b'e';
-        match self.de.peek_char() {
-            Some(END) => Ok(()),
-            Some(ch) => Err(self.de.unexpected_token(ch)),
-            None => Err(self.de.unexpected_eof()),
+        match try!(self.peek_char()) {
+            Some(ch) if ch == variant_open => {
+                try!(self.next_char());
+                try!(self.begin_value(true));
+                let value = try!(visitor.visit(VariantVisitor::new(self)));
+                match try!(self.next_char()) {
+                    END => {
+                        self.end_container();
+                        Ok(value)
+                    }
+                    ch => Err(self.unexpected_token(ch)),
+                }
+            }
+            Some(b'0'...b'9') => visitor.visit(UnitVariantVisitor::new(self)),
+            Some(ch) => Err(self.unexpected_peeked_token(ch)),
+            None => Err(self.unexpected_eof()),
         }
     }
-}
+
+    fn deserialize_seq<V>(&mut self, visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        try!(self.expect_container(b'l'));
+        self.deserialize(visitor)
+    }
+
+    fn deserialize_seq_fixed_size<V>(&mut self, _len: usize, visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V>(&mut self, _len: usize, visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    /// Tuple structs are read from a list holding exactly as many elements as the struct has
+    /// fields. Any other length fails with `ErrorCode::TupleStructLength`.
+    fn deserialize_tuple_struct<V>(&mut self,
+                                   name: &'static str,
+                                   len: usize,
+                                   mut visitor: V)
+                                   -> Result<V::Value>
+        where V: de::Visitor
+    {
+        try!(self.expect_container(b'l'));
+        try!(self.next_char());
+        try!(self.begin_value(true));
+        visitor.visit_seq(SeqVisitor::with_len(self, name, len))
+    }
+
+    fn deserialize_map<V>(&mut self, visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        try!(self.expect_container(b'd'));
+        self.deserialize(visitor)
+    }
+
+    fn deserialize_struct<V>(&mut self,
+                             _name: &'static str,
+                             fields: &'static [&'static str],
+                             mut visitor: V)
+                             -> Result<V::Value>
+        where V: de::Visitor
+    {
+        try!(self.expect_container(b'd'));
+        try!(self.next_char());
+        try!(self.begin_value(true));
+        visitor.visit_map(MapVisitor::with_fields(self, fields))
+    }
+
+    deserialize_int!(deserialize_u8, u8, visit_u8);
+    deserialize_int!(deserialize_u16, u16, visit_u16);
+    deserialize_int!(deserialize_u32, u32, visit_u32);
+    deserialize_int!(deserialize_u64, u64, visit_u64);
+    deserialize_int!(deserialize_usize, usize, visit_usize);
+    deserialize_int!(deserialize_i8, i8, visit_i8);
+    deserialize_int!(deserialize_i16, i16, visit_i16);
+    deserialize_int!(deserialize_i32, i32, visit_i32);
+    deserialize_int!(deserialize_i64, i64, visit_i64);
+    deserialize_int!(deserialize_isize, isize, visit_isize);
+
+    forward_to_deserialize! {
+        f32 f64 char struct_field
+    }
+}
+
+struct MapVisitor<'a, R: Read + 'a> {
+    de: &'a mut Deserializer<R>,
+    fields: Option<&'static [&'static str]>,
+    prev_key: Option<Vec<u8>>,
+}
+
+impl<'a, R: Read + 'a> MapVisitor<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
+        MapVisitor {
+            de: de,
+            fields: None,
+            prev_key: None,
+        }
+    }
+
+    /// Visits the dict holding a struct's fields. Keys naming one of `fields` are handed to the
+    /// key's visitor as that field name, and any other key as its raw bytes, so unknown keys need
+    /// not be valid UTF-8. How keys are matched, and whether unknown ones are allowed, is up to
+    /// the deserializer's `case_insensitive_fields` and `deny_unknown_fields` options.
+    fn with_fields(de: &'a mut Deserializer<R>, fields: &'static [&'static str]) -> Self {
+        MapVisitor {
+            de: de,
+            fields: Some(fields),
+            prev_key: None,
+        }
+    }
+
+    /// Reads a key and hands it to the key's visitor. When key order is checked, each key must sort
+    /// strictly after the one before it, compared as raw bytes.
+    fn visit_raw_key<K>(&mut self) -> Result<K>
+        where K: de::Deserialize
+    {
+        let start = self.de.reader.position();
+        let ch = try!(self.de.next_char());
+        try!(self.de.begin_value(false));
+        let key = try!(self.de.read_string(ch));
+        if self.de.check_key_order {
+            if let Some(ref prev) = self.prev_key {
+                if key == *prev {
+                    return Err(Error::Syntax(ErrorCode::DuplicateKey, start));
+                }
+                if key < *prev {
+                    return Err(Error::Syntax(ErrorCode::UnsortedKeys, start));
+                }
+            }
+        }
+        let result = match (self.fields, str::from_utf8(&key)) {
+            (Some(fields), _) => {
+                match self.find_field(fields, &key) {
+                    Some(field) => {
+                        let mut de: de::value::StrDeserializer<Error> = field.into_deserializer();
+                        de::Deserialize::deserialize(&mut de)
+                    }
+                    None if self.de.deny_unknown_fields => {
+                        let code = ErrorCode::UnknownField {
+                            field: String::from_utf8_lossy(&key).into_owned(),
+                            expected: fields,
+                        };
+                        return Err(Error::Syntax(code, start));
+                    }
+                    None => {
+                        let mut de: de::value::BytesDeserializer<Error> = Bytes::new(&key)
+                            .into_deserializer();
+                        de::Deserialize::deserialize(&mut de)
+                    }
+                }
+            }
+            (None, Ok(key)) => {
+                let mut de: de::value::StrDeserializer<Error> = key.into_deserializer();
+                de::Deserialize::deserialize(&mut de)
+            }
+            (None, Err(_)) => {
+                let mut de: de::value::BytesDeserializer<Error> = Bytes::new(&key)
+                    .into_deserializer();
+                de::Deserialize::deserialize(&mut de)
+            }
+        };
+        self.prev_key = Some(key);
+        result.map_err(|err| match err {
+            Error::Syntax(code, 0) => Error::Syntax(code, start),
+            err => err,
+        })
+    }
+
+    /// Finds the field named by `key`, trying an exact match before one that ignores case.
+    fn find_field(&self, fields: &'static [&'static str], key: &[u8]) -> Option<&'static str> {
+        let exact = fields.iter().find(|field| field.as_bytes() == key);
+        let found = if exact.is_none() && self.de.case_insensitive_fields {
+            fields.iter().find(|field| field.as_bytes().eq_ignore_ascii_case(key))
+        } else {
+            exact
+        };
+        found.cloned()
+    }
+}
+
+impl<'a, R: Read + 'a> de::MapVisitor for MapVisitor<'a, R> {
+    type Error = Error;
+
+    fn visit_key<K>(&mut self) -> Result<Option<K>>
+        where K: de::Deserialize
+    {
+        const END: u8 = b'e';
+        match try!(self.de.peek_char()) {
+            Some(END) => Ok(None),
+            Some(ch) => {
+                match ch {
+                    b'0'...b'9' => Ok(Some(try!(self.visit_raw_key()))),
+                    _ => Err(self.de.unexpected_peeked_token(ch)),
+                }
+            }
+            _ => Err(self.de.unexpected_eof()),
+        }
+    }
+
+    fn visit_value<V>(&mut self) -> Result<V>
+        where V: de::Deserialize
+    {
+        self.de.deserialize_located()
+    }
+
+    fn end(&mut self) -> Result<()> {
+        const END: u8 = b'e';
+        match try!(self.de.next_char()) {
+            END => {
+                self.de.end_container();
+                Ok(())
+            }
+            ch => Err(self.de.unexpected_token(ch)),
+        }
+    }
+
+    fn missing_field<V>(&mut self, field: &'static str) -> Result<V>
+        where V: de::Deserialize
+    {
+        use std;
+
+        struct MissingFieldDeserializer(&'static str);
+
+        impl de::Deserializer for MissingFieldDeserializer {
+            type Error = de::value::Error;
+
+            fn deserialize<V>(&mut self, _visitor: V) -> std::result::Result<V::Value, Self::Error>
+                where V: de::Visitor
+            {
+                let &mut MissingFieldDeserializer(field) = self;
+                Err(de::value::Error::MissingField(field))
+            }
+
+            fn deserialize_option<V>(&mut self,
+                                     mut visitor: V)
+                                     -> std::result::Result<V::Value, Self::Error>
+                where V: de::Visitor
+            {
+                visitor.visit_none()
+            }
+
+            forward_to_deserialize! {
+                bool usize u8 u16 u32 u64 isize i8 i16 i32 i64 f32 f64 char str
+                string unit seq seq_fixed_size bytes map unit_struct
+                newtype_struct tuple_struct struct struct_field tuple enum
+                ignored_any
+            }
+        }
+
+        let mut de = MissingFieldDeserializer(field);
+        Ok(try!(de::Deserialize::deserialize(&mut de)))
+    }
+}
+
+struct SeqVisitor<'a, R: Read + 'a> {
+    de: &'a mut Deserializer<R>,
+    /// The name and length of the tuple struct being read, if any
+    expected: Option<(&'static str, usize)>,
+    count: usize,
+}
+
+impl<'a, R: Read + 'a> SeqVisitor<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
+        SeqVisitor {
+            de: de,
+            expected: None,
+            count: 0,
+        }
+    }
+
+    /// Visits the list holding a tuple struct's fields, which must have exactly `len` elements.
+    fn with_len(de: &'a mut Deserializer<R>, name: &'static str, len: usize) -> Self {
+        SeqVisitor {
+            de: de,
+            expected: Some((name, len)),
+            count: 0,
+        }
+    }
+
+    /// Counts the elements left in a list that turned out too long for its tuple struct, so the
+    /// error can say how many there were. The error points at the first element too many.
+    fn too_long(&mut self, name: &'static str, expected: usize) -> Error {
+        const END: u8 = b'e';
+        let start = self.de.reader.position();
+        let mut found = self.count;
+        loop {
+            match self.de.peek_char() {
+                Ok(Some(END)) => break,
+                Ok(Some(_)) => {
+                    if let Err(err) = self.de.deserialize_located::<de::impls::IgnoredAny>() {
+                        return err;
+                    }
+                    found += 1;
+                }
+                Ok(None) => return self.de.unexpected_eof(),
+                Err(err) => return err,
+            }
+        }
+        let code = ErrorCode::TupleStructLength {
+            name: name,
+            expected: expected,
+            found: found,
+        };
+        Error::Syntax(code, start)
+    }
+}
+
+impl<'a, R: Read + 'a> de::SeqVisitor for SeqVisitor<'a, R> {
+    type Error = Error;
+
+    fn visit<V>(&mut self) -> Result<Option<V>>
+        where V: de::Deserialize
+    {
+        const END: u8 = b'e';
+        match (try!(self.de.peek_char()), self.expected) {
+            (Some(END), Some((name, expected))) if self.count < expected => {
+                let code = ErrorCode::TupleStructLength {
+                    name: name,
+                    expected: expected,
+                    found: self.count,
+                };
+                Err(Error::Syntax(code, self.de.reader.position()))
+            }
+            (Some(END), _) => Ok(None),
+            (Some(_), _) => {
+                let value = try!(self.de.deserialize_located());
+                self.count += 1;
+                Ok(Some(value))
+            }
+            (None, _) => Err(self.de.unexpected_eof()),
+        }
+    }
+
+    fn end(&mut self) -> Result<()> {
+        const END: u8 = b'e';
+        if let Some((name, expected)) = self.expected {
+            match try!(self.de.peek_char()) {
+                Some(END) | None => {}
+                Some(_) => return Err(self.too_long(name, expected)),
+            }
+        }
+        match try!(self.de.next_char()) {
+            END => {
+                self.de.end_container();
+                Ok(())
+            }
+            ch => Err(self.de.unexpected_token(ch)),
+        }
+    }
+}
+
+struct VariantVisitor<'a, R: Read + 'a> {
+    de: &'a mut Deserializer<R>,
+}
+
+impl<'a, R: Read + 'a> VariantVisitor<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
+        VariantVisitor { de: de }
+    }
+}
+
+impl<'a, R: Read + 'a> de::VariantVisitor for VariantVisitor<'a, R> {
+    type Error = Error;
+
+    fn visit_variant<V>(&mut self) -> Result<V>
+        where V: de::Deserialize
+    {
+        self.de.deserialize_located()
+    }
+
+    fn visit_newtype<T>(&mut self) -> Result<T>
+        where T: de::Deserialize
+    {
+        self.de.deserialize_located()
+    }
+
+    fn visit_tuple<V>(&mut self, _len: usize, visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        de::Deserializer::deserialize(self.de, visitor)
+    }
+
+    fn visit_struct<V>(&mut self,
+                       _fields: &'static [&'static str],
+                       visitor: V)
+                       -> Result<V::Value>
+        where V: de::Visitor
+    {
+        de::Deserializer::deserialize(self.de, visitor)
+    }
+}
+
+struct UnitVariantVisitor<'a, R: Read + 'a> {
+    de: &'a mut Deserializer<R>,
+}
+
+impl<'a, R: Read + 'a> UnitVariantVisitor<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
+        UnitVariantVisitor { de: de }
+    }
+}
+
+impl<'a, R: Read + 'a> de::VariantVisitor for UnitVariantVisitor<'a, R> {
+    type Error = Error;
+
+    fn visit_variant<V>(&mut self) -> Result<V>
+        where V: de::Deserialize
+    {
+        self.de.deserialize_located()
+    }
+
+    fn visit_unit(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn visit_newtype<T>(&mut self) -> Result<T>
+        where T: de::Deserialize
+    {
+        Err(de::Error::invalid_type(de::Type::UnitVariant))
+    }
+
+    fn visit_tuple<V>(&mut self, _len: usize, _visitor: V) -> Result<V::Value>
+        where V: de::Visitor
+    {
+        Err(de::Error::invalid_type(de::Type::UnitVariant))
+    }
+
+    fn visit_struct<V>(&mut self,
+                       _fields: &'static [&'static str],
+                       _visitor: V)
+                       -> Result<V::Value>
+        where V: de::Visitor
+    {
+        Err(de::Error::invalid_type(de::Type::UnitVariant))
+    }
+}
+
+/// Reads a sequence of values laid out back to back in a single input, such as a stream of
+/// messages, yielding each in turn. Iteration ends cleanly at the end of the input, and stops for
+/// good after the first error.
+pub struct StreamDeserializer<R, T>
+    where R: Read
+{
+    de: Deserializer<R>,
+    max_values: Option<usize>,
+    count: usize,
+    done: bool,
+    output: PhantomData<T>,
+}
+
+impl<R, T> StreamDeserializer<R, T>
+    where R: Read,
+          T: de::Deserialize
+{
+    pub fn new(reader: R) -> Self {
+        StreamDeserializer {
+            de: Deserializer::new(reader),
+            max_values: None,
+            count: 0,
+            done: false,
+            output: PhantomData,
+        }
+    }
+
+    /// Stops after `max_values` values, to bound the work done on an untrusted stream. If more
+    /// input follows, a final `ErrorCode::ValueLimitExceeded` is yielded so that the cut-off
+    /// can't be mistaken for the end of the stream.
+    pub fn with_max_values(mut self, max_values: usize) -> Self {
+        self.max_values = Some(max_values);
+        self
+    }
+}
+
+impl<R, T> Iterator for StreamDeserializer<R, T>
+    where R: Read,
+          T: de::Deserialize
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.done {
+            return None;
+        }
+        match self.de.peek_char() {
+            Ok(Some(_)) => {}
+            Ok(None) => return None,
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+        if let Some(max) = self.max_values {
+            if self.count == max {
+                self.done = true;
+                return Some(Err(self.de.syntax_error(ErrorCode::ValueLimitExceeded(max))));
+            }
+        }
+        self.count += 1;
+        let result = de::Deserialize::deserialize(&mut self.de);
+        self.done = result.is_err();
+        Some(result)
+    }
+}
 
 fn from_read<R, T>(read: R) -> Result<T>
     where R: Read,
@@ -304,8 +1405,1505 @@ pub fn from_slice<T>(s: &[u8]) -> Result<T>
     from_read(read::SliceRead::new(s))
 }
 
+/// Decodes the value at the start of `s`, returning it along with the bytes that follow it. This
+/// suits protocols that put binary data straight after a bencoded header, such as the metadata
+/// pieces of BEP 9, where `from_slice` would reject the payload as trailing characters.
+pub fn from_slice_partial<T>(s: &[u8]) -> Result<(T, &[u8])>
+    where T: de::Deserialize
+{
+    let mut de = Deserializer::new(read::SliceRead::new(s));
+    let value = try!(de::Deserialize::deserialize(&mut de));
+    Ok((value, &s[de.position()..]))
+}
+
 pub fn from_string<T>(s: String) -> Result<T>
     where T: de::Deserialize
 {
     from_read(read::StringRead::new(&s))
 }
+
+/// Decodes a value from bencode held in a `str`, without needing an owned `String` as
+/// `from_string` does.
+///
+/// ```rust
+/// let decoded: Vec<String> = serde_bencode::from_str("l4:spam4:eggse").unwrap();
+/// assert_eq!(decoded, vec!["spam", "eggs"]);
+/// ```
+pub fn from_str<T>(s: &str) -> Result<T>
+    where T: de::Deserialize
+{
+    from_slice(s.as_bytes())
+}
+
+/// An alias for `from_slice`, for those used to the `encode`/`decode` naming of other bencode
+/// libraries.
+///
+/// ```rust
+/// let decoded: Vec<i64> = serde_bencode::decode(b"li1ei2ei3ee").unwrap();
+/// assert_eq!(decoded, vec![1, 2, 3]);
+/// ```
+pub fn decode<T>(s: &[u8]) -> Result<T>
+    where T: de::Deserialize
+{
+    from_slice(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::ser;
+
+    use super::*;
+    use error::{Error, ErrorCode, Result};
+    use ser::{Serializer, VariantEncoding};
+
+    fn assert_syntax_error<T>(result: Result<T>, expected_code: ErrorCode, expected_pos: usize) {
+        match result {
+            Err(Error::Syntax(code, pos)) => {
+                assert_eq!(code, expected_code);
+                assert_eq!(pos, expected_pos);
+            }
+            Err(err) => panic!("Expected a syntax error, got {:?}", err),
+            Ok(_) => panic!("Expected a syntax error"),
+        }
+    }
+
+    fn unexpected(tok: &str) -> ErrorCode {
+        ErrorCode::UnexpectedToken(tok.to_string())
+    }
+
+    #[test]
+    fn test_error_position_in_int() {
+        assert_syntax_error(from_slice::<i64>(b"i12xe"), unexpected("x"), 3);
+        assert_syntax_error(from_slice::<i64>(b"i-0e"), unexpected("0"), 2);
+        assert_syntax_error(from_slice::<i64>(b"ie"), unexpected("e"), 1);
+    }
+
+    #[test]
+    fn test_plus_sign_rejected() {
+        assert_syntax_error(from_slice::<i64>(b"i+5e"), ErrorCode::PlusSign, 1);
+        assert_syntax_error(from_slice::<u64>(b"i+0e"), ErrorCode::PlusSign, 1);
+        assert_syntax_error(from_reader::<_, Vec<i64>>(&b"li1ei+5ee"[..]),
+                            ErrorCode::PlusSign,
+                            5);
+        assert_syntax_error(from_slice::<::value::Value>(b"i+5e"), ErrorCode::PlusSign, 1);
+        assert_syntax_error(from_slice::<i64>(b"i-+5e"), unexpected("+"), 2);
+
+        let err = from_slice::<i64>(b"i+5e").unwrap_err();
+        assert_eq!(format!("{}", err), "At position 1: Integers may not have a leading + sign");
+    }
+
+    #[test]
+    fn test_error_position_in_string_length() {
+        assert_syntax_error(from_slice::<String>(b"3;abc"), ErrorCode::InvalidLengthPrefix, 1);
+        assert_syntax_error(from_slice::<String>(b"0;"), ErrorCode::InvalidLengthPrefix, 1);
+    }
+
+    #[test]
+    fn test_invalid_length_prefix() {
+        // A non-digit in the middle of the length
+        assert_syntax_error(from_slice::<String>(b"1x2:abc"), ErrorCode::InvalidLengthPrefix, 1);
+        assert_syntax_error(from_slice::<Vec<String>>(b"l12-:abce"),
+                            ErrorCode::InvalidLengthPrefix,
+                            3);
+        // The colon is missing altogether
+        assert_syntax_error(from_slice::<String>(b"3abc"), ErrorCode::InvalidLengthPrefix, 1);
+        assert_syntax_error(from_slice::<BTreeMap<String, i64>>(b"d1ai1ee"),
+                            ErrorCode::InvalidLengthPrefix,
+                            2);
+        // Running out of input is still reported as such
+        assert_syntax_error(from_slice::<String>(b"12"), ErrorCode::UnexpectedEOF, 2);
+    }
+
+    #[test]
+    fn test_length_prefix_leading_zeros() {
+        assert_syntax_error(from_slice::<String>(b"05:xxxxx"), ErrorCode::InvalidLengthPrefix, 1);
+        assert_syntax_error(from_slice::<String>(b"000000000000005:hello"),
+                            ErrorCode::InvalidLengthPrefix,
+                            1);
+        assert_syntax_error(from_slice::<BTreeMap<String, i64>>(b"d01:ai1ee"),
+                            ErrorCode::InvalidLengthPrefix,
+                            2);
+        assert_eq!(from_slice::<String>(b"0:").unwrap(), "");
+        assert_eq!(from_slice::<String>(b"10:0123456789").unwrap(), "0123456789");
+    }
+
+    #[test]
+    fn test_max_length_digits() {
+        fn with_limit(input: &[u8], max: usize) -> Result<String> {
+            let mut de = Deserializer::new(read::SliceRead::new(input)).max_length_digits(max);
+            de::Deserialize::deserialize(&mut de)
+        }
+
+        assert_eq!(with_limit(b"10:0123456789", 2).unwrap(), "0123456789");
+        assert_syntax_error(with_limit(b"100:", 2), ErrorCode::LengthPrefixTooLong(2), 2);
+        // The first digit counts towards the limit
+        assert_eq!(with_limit(b"5:hello", 1).unwrap(), "hello");
+        assert_syntax_error(with_limit(b"5:hello", 0), ErrorCode::LengthPrefixTooLong(0), 0);
+        assert_syntax_error(with_limit(b"0:", 0), ErrorCode::LengthPrefixTooLong(0), 0);
+        // The prefix is rejected before the rest of it is read
+        assert_syntax_error(with_limit(b"1000000000000000000000000000000", 3),
+                            ErrorCode::LengthPrefixTooLong(3),
+                            3);
+        // Integers are not affected
+        let mut de = Deserializer::new(read::SliceRead::new(b"i12345e")).max_length_digits(2);
+        assert_eq!(<i64 as de::Deserialize>::deserialize(&mut de).unwrap(), 12345);
+    }
+
+    #[test]
+    fn test_error_position_of_peeked_token() {
+        assert_syntax_error(from_slice::<BTreeMap<String, i64>>(b"d1:ai1exe"),
+                            unexpected("x"),
+                            7);
+    }
+
+    #[test]
+    fn test_error_position_at_end_of_input() {
+        assert_syntax_error(from_slice::<i64>(b"i12"), ErrorCode::UnexpectedEOF, 3);
+        assert_syntax_error(from_slice::<i64>(b"i1ex"), ErrorCode::UnexpectedTrailingChars, 3);
+    }
+
+    #[test]
+    fn test_u64_round_trip() {
+        use ser::to_vec;
+
+        for &x in &[0, i64::max_value() as u64, i64::max_value() as u64 + 1, u64::max_value()] {
+            let encoded = to_vec(&x).unwrap();
+            assert_eq!(from_slice::<u64>(&encoded).unwrap(), x);
+        }
+        assert_eq!(from_slice::<i64>(b"i-9223372036854775808e").unwrap(),
+                   i64::min_value());
+
+        assert!(from_slice::<i64>(b"i9223372036854775808e").is_err());
+        assert_syntax_error(from_slice::<de::impls::IgnoredAny>(b"i-9223372036854775809e"),
+                            ErrorCode::UnrepresentableInteger(-9223372036854775809),
+                            22);
+    }
+
+    #[test]
+    fn test_deserialize_bytes() {
+        use serde::bytes::ByteBuf;
+
+        let buf = from_slice::<ByteBuf>(b"2:\xff\x00").unwrap();
+        assert_eq!(&buf[..], &[0xff, 0x00]);
+        assert_syntax_error(from_slice::<ByteBuf>(b"i1e"),
+                            ErrorCode::InvalidType(de::Type::I64),
+                            0);
+        assert_syntax_error(from_slice::<ByteBuf>(b"li1ei2ee"),
+                            ErrorCode::InvalidType(de::Type::Seq),
+                            0);
+        assert_syntax_error(from_slice::<Vec<ByteBuf>>(b"l1:ad1:ai1eee"),
+                            ErrorCode::InvalidType(de::Type::Map),
+                            4);
+        assert_syntax_error(from_slice::<ByteBuf>(b""), ErrorCode::UnexpectedEOF, 0);
+
+        // A torrent's `pieces` is a run of 20-byte SHA1 hashes, rarely valid UTF-8
+        let mut input = b"40:".to_vec();
+        let pieces: Vec<u8> = (0..40).map(|i| 0xff - i).collect();
+        input.extend_from_slice(&pieces);
+        assert_eq!(&from_slice::<ByteBuf>(&input).unwrap()[..], &pieces[..]);
+    }
+
+    #[test]
+    fn test_deserialize_optional_bytes() {
+        use serde::bytes::ByteBuf;
+
+        let buf = from_slice::<Option<ByteBuf>>(b"2:\x00\x7f").unwrap();
+        assert_eq!(buf, Some(ByteBuf::from(vec![0x00, 0x7f])));
+        assert_eq!(from_slice::<Option<ByteBuf>>(b"").unwrap(), None);
+
+        let map: BTreeMap<String, Option<ByteBuf>> = from_slice(b"d1:a1:\x00e").unwrap();
+        assert_eq!(map["a"], Some(ByteBuf::from(vec![0x00])));
+    }
+
+    #[test]
+    fn test_integer_out_of_range() {
+        let err = from_slice::<u8>(b"i256e").unwrap_err();
+        assert_eq!(format!("{}", err), "At position 0: Value 256 out of range for u8");
+
+        assert_syntax_error(from_slice::<Vec<i8>>(b"li1ei-129ee"),
+                            ErrorCode::IntegerOutOfRange {
+                                value: -129,
+                                target: "i8",
+                            },
+                            4);
+        assert_syntax_error(from_slice::<u64>(b"i-1e"),
+                            ErrorCode::IntegerOutOfRange {
+                                value: -1,
+                                target: "u64",
+                            },
+                            0);
+
+        assert_eq!(from_slice::<u8>(b"i255e").unwrap(), 255);
+        assert_eq!(from_slice::<i8>(b"i-128e").unwrap(), -128);
+    }
+
+    /// Hands out its input one byte per call, failing once when `fail_at` bytes have been read.
+    struct FailingRead {
+        input: &'static [u8],
+        pos: usize,
+        fail_at: Option<usize>,
+    }
+
+    impl FailingRead {
+        fn new(input: &'static [u8], fail_at: usize) -> Self {
+            FailingRead {
+                input: input,
+                pos: 0,
+                fail_at: Some(fail_at),
+            }
+        }
+    }
+
+    impl io::Read for FailingRead {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail_at == Some(self.pos) {
+                self.fail_at = None;
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "read failed"));
+            }
+            if self.pos == self.input.len() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.input[self.pos];
+            self.pos += 1;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_read_errors_are_not_end_of_input() {
+        fn is_io_error<T: ::std::fmt::Debug>(result: Result<T>) -> bool {
+            match result {
+                Err(Error::Io(ref err)) => err.to_string() == "read failed",
+                other => panic!("Expected an I/O error, got {:?}", other),
+            }
+        }
+
+        // Failing in the middle of a list, and just after a complete value
+        assert!(is_io_error(from_reader::<_, Vec<i64>>(FailingRead::new(b"li1ei2ee", 4))));
+        assert!(is_io_error(from_reader::<_, i64>(FailingRead::new(b"i1e", 3))));
+
+        let reader = io::BufReader::new(FailingRead::new(b"li1ei2ee", 4));
+        let mut de = Deserializer::from_reader(reader);
+        assert!(is_io_error(<Vec<i64> as de::Deserialize>::deserialize(&mut de)));
+        let reader = io::BufReader::new(FailingRead::new(b"i1e", 3));
+        let mut de = Deserializer::from_reader(reader);
+        assert_eq!(<i64 as de::Deserialize>::deserialize(&mut de).unwrap(), 1);
+        assert!(is_io_error(de.end()));
+
+        let reader = io::BufReader::new(FailingRead::new(b"i1ei2e", 3));
+        let mut stream = StreamDeserializer::<_, i64>::new(read::IoRead::new(reader));
+        assert_eq!(stream.next().unwrap().unwrap(), 1);
+        assert!(is_io_error(stream.next().unwrap()));
+        assert!(stream.next().is_none());
+    }
+
+    /// Counts the bytes read one at a time, while skipping in bulk like a `SliceRead`.
+    struct CountingRead<'a> {
+        inner: read::SliceRead<'a>,
+        chars_read: usize,
+    }
+
+    impl<'a> Read for CountingRead<'a> {
+        fn next_char(&mut self) -> Option<Result<u8>> {
+            self.chars_read += 1;
+            self.inner.next_char()
+        }
+
+        fn peek_char(&mut self) -> Option<Result<u8>> {
+            self.inner.peek_char()
+        }
+
+        fn position(&self) -> usize {
+            self.inner.position()
+        }
+
+        fn skip(&mut self, n: usize) -> Result<usize> {
+            self.inner.skip(n)
+        }
+    }
+
+    #[test]
+    fn test_ignored_string_is_skipped_without_reading() {
+        let pieces = vec![0xab; 1 << 20];
+        let mut input = b"d4:name7:example6:pieces1048576:".to_vec();
+        input.extend_from_slice(&pieces);
+        input.extend_from_slice(b"e");
+
+        let mut de = Deserializer::new(CountingRead {
+            inner: read::SliceRead::new(&input),
+            chars_read: 0,
+        });
+        let map: BTreeMap<String, de::impls::IgnoredAny> = de::Deserialize::deserialize(&mut de)
+            .unwrap();
+        de.end().unwrap();
+        assert_eq!(map.len(), 2);
+        assert!(de.reader.chars_read < 100,
+                "read {} bytes one at a time",
+                de.reader.chars_read);
+    }
+
+    #[test]
+    fn test_ignored_string_past_end_of_input() {
+        assert_syntax_error(from_slice::<de::impls::IgnoredAny>(b"10:abc"),
+                            ErrorCode::UnexpectedEOF,
+                            6);
+        let iter = b"10:abc".iter().map(|&b| Ok(b));
+        assert_syntax_error(from_iter::<_, de::impls::IgnoredAny>(iter),
+                            ErrorCode::UnexpectedEOF,
+                            6);
+    }
+
+    #[test]
+    fn test_nested_lists() {
+        assert_eq!(from_slice::<Vec<Vec<i64>>>(b"lli1eeli2ei3eee").unwrap(),
+                   vec![vec![1], vec![2, 3]]);
+
+        let map = from_slice::<BTreeMap<String, Vec<i64>>>(b"d1:ali1ee1:blee").unwrap();
+        assert_eq!(map["a"], vec![1]);
+        assert_eq!(map["b"], vec![]);
+
+        assert_syntax_error(from_slice::<Vec<i64>>(b"li1eee"),
+                            ErrorCode::UnexpectedTrailingChars,
+                            5);
+    }
+
+    #[test]
+    fn test_deserialize_unit() {
+        from_slice::<()>(b"de").unwrap();
+        assert_eq!(from_slice::<Vec<()>>(b"ldedee").unwrap(), vec![(), ()]);
+
+        assert_syntax_error(from_slice::<()>(b""), ErrorCode::UnexpectedEOF, 0);
+        assert_syntax_error(from_slice::<()>(b"d1:ai1ee"), unexpected("1"), 1);
+        assert_syntax_error(from_slice::<()>(b"le"), unexpected("l"), 0);
+    }
+
+    #[test]
+    fn test_unit_round_trip() {
+        use ser::to_vec;
+
+        let encoded = to_vec(&()).unwrap();
+        assert_eq!(encoded, b"de");
+        from_slice::<()>(&encoded).unwrap();
+
+        let x = ((), 1, ());
+        let encoded = to_vec(&x).unwrap();
+        assert_eq!(encoded, &b"ldei1edee"[..]);
+        assert_eq!(from_slice::<((), i64, ())>(&encoded).unwrap(), x);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Message {
+        Ping,
+        Data(i64),
+        Hashes(::serde::bytes::ByteBuf),
+    }
+
+    impl ser::Serialize for Message {
+        fn serialize<S>(&self, serializer: &mut S) -> ::std::result::Result<(), S::Error>
+            where S: ser::Serializer
+        {
+            match *self {
+                Message::Ping => serializer.serialize_unit_variant("Message", 0, "Ping"),
+                Message::Data(n) => serializer.serialize_newtype_variant("Message", 1, "Data", n),
+                Message::Hashes(ref hashes) => {
+                    serializer.serialize_newtype_variant("Message", 2, "Hashes", hashes)
+                }
+            }
+        }
+    }
+
+    impl de::Deserialize for Message {
+        fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<Message, D::Error>
+            where D: de::Deserializer
+        {
+            struct MessageVisitor;
+
+            impl de::EnumVisitor for MessageVisitor {
+                type Value = Message;
+
+                fn visit<V>(&mut self, mut visitor: V) -> ::std::result::Result<Message, V::Error>
+                    where V: de::VariantVisitor
+                {
+                    let variant: String = try!(visitor.visit_variant());
+                    match &variant[..] {
+                        "Ping" => visitor.visit_unit().map(|()| Message::Ping),
+                        "Data" => visitor.visit_newtype().map(Message::Data),
+                        "Hashes" => visitor.visit_newtype().map(Message::Hashes),
+                        _ => Err(de::Error::unknown_variant(&variant)),
+                    }
+                }
+            }
+
+            const VARIANTS: &[&str] = &["Ping", "Data", "Hashes"];
+            deserializer.deserialize_enum("Message", VARIANTS, MessageVisitor)
+        }
+    }
+
+    fn round_trip_with(encoding: VariantEncoding, messages: &[Message]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        {
+            let mut ser = Serializer::new(&mut encoded).variant_encoding(encoding);
+            ser::Serialize::serialize(messages, &mut ser).unwrap();
+        }
+        let mut de = Deserializer::new(read::SliceRead::new(&encoded)).variant_encoding(encoding);
+        let decoded: Vec<Message> = de::Deserialize::deserialize(&mut de).unwrap();
+        de.end().unwrap();
+        assert_eq!(decoded, messages);
+        encoded
+    }
+
+    #[test]
+    fn test_variant_encoding_round_trip() {
+        let messages = [Message::Data(42), Message::Ping];
+        assert_eq!(round_trip_with(VariantEncoding::Dict, &messages),
+                   &b"ld4:Datai42ee4:Pinge"[..]);
+        assert_eq!(round_trip_with(VariantEncoding::List, &messages),
+                   &b"ll4:Datai42ee4:Pinge"[..]);
+    }
+
+    #[test]
+    fn test_newtype_variant_with_binary_payload() {
+        let hashes: Vec<u8> = vec![0xff, 0x00, 0xfe, b'e', b':', 0x80];
+        let messages = [Message::Hashes(hashes.clone().into()), Message::Data(1)];
+        let mut expected = b"ld6:Hashes6:".to_vec();
+        expected.extend_from_slice(&hashes);
+        expected.extend_from_slice(b"ed4:Datai1eee");
+        assert_eq!(round_trip_with(VariantEncoding::Dict, &messages), expected);
+
+        let mut expected = b"ll6:Hashes6:".to_vec();
+        expected.extend_from_slice(&hashes);
+        expected.extend_from_slice(b"el4:Datai1eee");
+        assert_eq!(round_trip_with(VariantEncoding::List, &messages), expected);
+
+        // Readers that copy strings byte by byte see the same payload
+        let mut input = b"d6:Hashes6:".to_vec();
+        input.extend_from_slice(&hashes);
+        input.push(b'e');
+        assert_eq!(from_reader::<_, Message>(&input[..]).unwrap(),
+                   Message::Hashes(hashes.into()));
+    }
+
+    #[test]
+    fn test_variant_encoding_mismatch() {
+        let mut de = Deserializer::new(read::SliceRead::new(b"l4:Datai42ee"));
+        assert!(<Message as de::Deserialize>::deserialize(&mut de).is_err());
+        let mut de = Deserializer::new(read::SliceRead::new(b"d4:Datai42ee"))
+            .variant_encoding(VariantEncoding::List);
+        assert!(<Message as de::Deserialize>::deserialize(&mut de).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_newtype_struct() {
+        #[derive(Debug, PartialEq)]
+        struct Meters(i64);
+
+        impl de::Deserialize for Meters {
+            fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<Meters, D::Error>
+                where D: de::Deserializer
+            {
+                struct MetersVisitor;
+
+                impl de::Visitor for MetersVisitor {
+                    type Value = Meters;
+
+                    fn visit_newtype_struct<D>(&mut self,
+                                               deserializer: &mut D)
+                                               -> ::std::result::Result<Meters, D::Error>
+                        where D: de::Deserializer
+                    {
+                        de::Deserialize::deserialize(deserializer).map(Meters)
+                    }
+                }
+
+                deserializer.deserialize_newtype_struct("Meters", MetersVisitor)
+            }
+        }
+
+        assert_eq!(from_slice::<Meters>(b"i42e").unwrap(), Meters(42));
+        assert_eq!(from_slice::<Vec<Meters>>(b"li1ei2ee").unwrap(),
+                   vec![Meters(1), Meters(2)]);
+    }
+
+    #[test]
+    fn test_invalid_type_message() {
+        let err = from_slice::<String>(b"i42e").unwrap_err();
+        assert_eq!(format!("{}", err),
+                   "At position 0: Invalid type: unexpected bencode integer");
+
+        let err = from_slice::<i64>(b"4:spam").unwrap_err();
+        assert_eq!(format!("{}", err),
+                   "At position 0: Invalid type: unexpected bencode byte string");
+
+        let err = from_slice::<i64>(b"li1ee").unwrap_err();
+        assert_eq!(format!("{}", err),
+                   "At position 0: Invalid type: unexpected bencode list");
+
+        let err = from_slice::<Vec<i64>>(b"de").unwrap_err();
+        assert_eq!(format!("{}", err),
+                   "At position 0: Invalid type: unexpected bencode dictionary");
+    }
+
+    #[test]
+    fn test_large_string_read_into_one_allocation() {
+        let pieces: Vec<u8> = (0..3 << 20).map(|i| (i % 251) as u8).collect();
+        let mut input = format!("{}:", pieces.len()).into_bytes();
+        input.extend_from_slice(&pieces);
+
+        let mut de = Deserializer::from_reader(&input[..]);
+        let ch = de.next_char().unwrap();
+        let buf = de.read_string(ch).unwrap();
+        assert_eq!(buf.capacity(), pieces.len());
+        assert!(buf == pieces);
+
+        let buf = from_reader::<_, ::serde::bytes::ByteBuf>(&input[..]).unwrap();
+        assert!(buf[..] == pieces[..]);
+        let buf = from_slice::<::serde::bytes::ByteBuf>(&input).unwrap();
+        assert!(buf[..] == pieces[..]);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_huge_string_length_fails_to_allocate() {
+        let len = i64::max_value() as usize;
+        let input = b"9223372036854775807:abc";
+        let mut de = Deserializer::from_reader(&input[..]).max_string_len(usize::max_value());
+        assert_syntax_error(<String as de::Deserialize>::deserialize(&mut de),
+                            ErrorCode::AllocationFailed(len),
+                            20);
+        // A slice knows up front that the string runs past the end
+        let mut de = Deserializer::new(read::SliceRead::new(input))
+            .max_string_len(usize::max_value());
+        assert_syntax_error(<String as de::Deserialize>::deserialize(&mut de),
+                            ErrorCode::UnexpectedEOF,
+                            23);
+    }
+
+    #[test]
+    fn test_default_max_string_len() {
+        let input = b"99999999999:";
+        assert_syntax_error(from_slice::<String>(input),
+                            ErrorCode::StringTooLong(99999999999),
+                            12);
+        assert_syntax_error(from_reader::<_, String>(&input[..]),
+                            ErrorCode::StringTooLong(99999999999),
+                            12);
+        assert_syntax_error(::value::from_slice_lenient(b"d1:a99999999999:e"),
+                            ErrorCode::StringTooLong(99999999999),
+                            16);
+
+        let mut input = format!("{}:", DEFAULT_MAX_STRING_LEN + 1).into_bytes();
+        let len = input.len();
+        assert_syntax_error(from_slice::<String>(&input),
+                            ErrorCode::StringTooLong(DEFAULT_MAX_STRING_LEN + 1),
+                            len);
+        input = format!("{}:", DEFAULT_MAX_STRING_LEN).into_bytes();
+        assert_syntax_error(from_slice::<String>(&input), ErrorCode::UnexpectedEOF, len);
+    }
+
+    #[test]
+    fn test_limits_apply_to_typed_deserialization() {
+        let mut de = Deserializer::new(read::SliceRead::new(b"lli1eeli2eee")).max_depth(2);
+        let value: Vec<Vec<i64>> = de::Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(value, vec![vec![1], vec![2]]);
+
+        let mut de = Deserializer::new(read::SliceRead::new(b"llli1eeee")).max_depth(2);
+        assert_syntax_error(<Vec<Vec<Vec<i64>>> as de::Deserialize>::deserialize(&mut de),
+                            ErrorCode::DepthLimitExceeded(2),
+                            3);
+
+        let mut de = Deserializer::new(read::SliceRead::new(b"li1ei2ei3ee")).max_nodes(3);
+        assert_syntax_error(<Vec<i64> as de::Deserialize>::deserialize(&mut de),
+                            ErrorCode::NodeLimitExceeded(3),
+                            8);
+
+        let mut de = Deserializer::new(read::SliceRead::new(b"5:hello")).max_string_len(4);
+        assert_syntax_error(<String as de::Deserialize>::deserialize(&mut de),
+                            ErrorCode::StringTooLong(5),
+                            2);
+    }
+
+    #[test]
+    fn test_default_max_depth() {
+        let deep = vec![b'l'; 100_000];
+        assert_syntax_error(from_slice::<::value::Value>(&deep),
+                            ErrorCode::DepthLimitExceeded(DEFAULT_MAX_DEPTH),
+                            DEFAULT_MAX_DEPTH + 1);
+        assert_syntax_error(from_slice::<de::impls::IgnoredAny>(&deep),
+                            ErrorCode::DepthLimitExceeded(DEFAULT_MAX_DEPTH),
+                            DEFAULT_MAX_DEPTH + 1);
+        assert_syntax_error(::value::from_slice_lenient(&deep),
+                            ErrorCode::DepthLimitExceeded(DEFAULT_MAX_DEPTH),
+                            DEFAULT_MAX_DEPTH + 1);
+
+        let mut nested = vec![b'l'; DEFAULT_MAX_DEPTH];
+        nested.extend(vec![b'e'; DEFAULT_MAX_DEPTH]);
+        from_slice::<::value::Value>(&nested).unwrap();
+
+        let mut de = Deserializer::with_max_depth(read::SliceRead::new(&nested), 1000);
+        <::value::Value as de::Deserialize>::deserialize(&mut de).unwrap();
+        let mut de = Deserializer::with_max_depth(read::SliceRead::new(&nested), 10);
+        assert_syntax_error(<::value::Value as de::Deserialize>::deserialize(&mut de),
+                            ErrorCode::DepthLimitExceeded(10),
+                            11);
+    }
+
+    #[test]
+    fn test_container_type_mismatch() {
+        assert_syntax_error(from_slice::<Vec<i64>>(b"d1:ai1ee"),
+                            ErrorCode::InvalidType(de::Type::Map),
+                            0);
+        assert_syntax_error(from_slice::<(i64, i64)>(b"i1e"),
+                            ErrorCode::InvalidType(de::Type::I64),
+                            0);
+        assert_syntax_error(from_slice::<[i64; 2]>(b"2:ab"),
+                            ErrorCode::InvalidType(de::Type::Bytes),
+                            0);
+        assert_syntax_error(from_slice::<BTreeMap<String, Vec<i64>>>(b"d1:a1:be"),
+                            ErrorCode::InvalidType(de::Type::Bytes),
+                            4);
+        assert_syntax_error(from_slice::<Vec<i64>>(b"x"), unexpected("x"), 0);
+        assert_syntax_error(from_slice::<Vec<i64>>(b""), ErrorCode::UnexpectedEOF, 0);
+
+        assert_syntax_error(from_slice::<BTreeMap<String, i64>>(b"li1ee"),
+                            ErrorCode::InvalidType(de::Type::Seq),
+                            0);
+        let err = from_slice::<BTreeMap<String, i64>>(b"li1ee").unwrap_err();
+        assert_eq!(err.to_string(),
+                   "At position 0: Invalid type: unexpected bencode list");
+    }
+
+    #[test]
+    fn test_from_reader_reads_lists_and_dicts() {
+        let value: BTreeMap<String, Vec<i64>> = from_reader(&b"d1:ali1ei2ee1:blee"[..]).unwrap();
+        assert_eq!(value["a"], vec![1, 2]);
+        assert!(value["b"].is_empty());
+        assert_syntax_error(from_reader::<_, Vec<i64>>(&b"li1ee!"[..]),
+                            ErrorCode::UnexpectedTrailingChars,
+                            5);
+    }
+
+    #[test]
+    fn test_reader_deserializer_reads_one_value_at_a_time() {
+        use std::io::{BufReader, Read};
+
+        let input: &[u8] = b"d1:ai1eeli2ei3ee4:spamrest";
+        // A small buffer makes the reader refill mid-value
+        let mut de = Deserializer::from_reader(BufReader::with_capacity(3, input));
+
+        let first: BTreeMap<String, i64> = de::Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(first["a"], 1);
+        let second: Vec<i64> = de::Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(second, vec![2, 3]);
+        let third: String = de::Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(third, "spam");
+
+        let mut rest = String::new();
+        de.into_inner().read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "rest");
+    }
+
+    #[test]
+    fn test_stream_deserializer() {
+        let stream = StreamDeserializer::new(read::SliceRead::new(b"i1ei2ei3e"));
+        let values: Vec<i64> = stream.map(Result::unwrap).collect();
+        assert_eq!(values, vec![1, 2, 3]);
+
+        let mut stream = StreamDeserializer::<_, i64>::new(read::SliceRead::new(b"i1e4:spami3e"));
+        assert_eq!(stream.next().unwrap().unwrap(), 1);
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_stream_deserializer_max_values() {
+        let mut stream = StreamDeserializer::<_, i64>::new(read::SliceRead::new(b"i1ei2ei3e"))
+            .with_max_values(2);
+        assert_eq!(stream.next().unwrap().unwrap(), 1);
+        assert_eq!(stream.next().unwrap().unwrap(), 2);
+        assert_syntax_error(stream.next().unwrap(), ErrorCode::ValueLimitExceeded(2), 6);
+        assert!(stream.next().is_none());
+
+        // Reaching the cap exactly at the end of the input is not an error
+        let stream = StreamDeserializer::<_, i64>::new(read::SliceRead::new(b"i1ei2e"))
+            .with_max_values(2);
+        assert_eq!(stream.map(Result::unwrap).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    /// An integer that must be even, checked once it has been read.
+    #[derive(Debug)]
+    struct Even(i64);
+
+    impl de::Deserialize for Even {
+        fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<Even, D::Error>
+            where D: de::Deserializer
+        {
+            let n = try!(i64::deserialize(deserializer));
+            if n % 2 != 0 {
+                return Err(de::Error::custom(format!("{} is odd", n)));
+            }
+            Ok(Even(n))
+        }
+    }
+
+    #[test]
+    fn test_custom_error_position() {
+        assert_eq!(from_slice::<Vec<Even>>(b"li2ee").unwrap()[0].0, 2);
+        assert_syntax_error(from_slice::<Vec<Even>>(b"li2ei4ei5ee"),
+                            ErrorCode::Custom("5 is odd".to_string()),
+                            7);
+        assert_syntax_error(from_slice::<BTreeMap<String, Vec<Even>>>(b"d1:ali2ee1:bli3eee"),
+                            ErrorCode::Custom("3 is odd".to_string()),
+                            13);
+        assert_syntax_error(from_slice::<Vec<Message>>(b"ld4:Pongdeee"),
+                            ErrorCode::Custom("Unknown variant `Pong`".to_string()),
+                            1);
+        // At the top level the value starts at 0 anyway
+        assert_syntax_error(from_slice::<Even>(b"i1e"),
+                            ErrorCode::Custom("1 is odd".to_string()),
+                            0);
+    }
+
+    /// A struct without fields, deserialized as serde's derive would.
+    #[derive(Debug, PartialEq)]
+    struct Empty {}
+
+    impl de::Deserialize for Empty {
+        fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<Empty, D::Error>
+            where D: de::Deserializer
+        {
+            struct EmptyVisitor;
+
+            impl de::Visitor for EmptyVisitor {
+                type Value = Empty;
+
+                fn visit_map<V>(&mut self, mut visitor: V) -> ::std::result::Result<Empty, V::Error>
+                    where V: de::MapVisitor
+                {
+                    while try!(visitor.visit_key::<de::impls::IgnoredAny>()).is_some() {
+                        try!(visitor.visit_value::<de::impls::IgnoredAny>());
+                    }
+                    try!(visitor.end());
+                    Ok(Empty {})
+                }
+            }
+
+            deserializer.deserialize_struct("Empty", &[], EmptyVisitor)
+        }
+    }
+
+    #[test]
+    fn test_deserialize_empty_struct() {
+        assert_eq!(from_slice::<Empty>(b"de").unwrap(), Empty {});
+        assert_eq!(from_slice::<Empty>(b"d1:ai1e1:bli1eee").unwrap(), Empty {});
+        assert_eq!(from_slice::<Vec<Empty>>(b"ldedee").unwrap(), vec![Empty {}, Empty {}]);
+        assert_syntax_error(from_slice::<Empty>(b"le"),
+                            ErrorCode::InvalidType(de::Type::Seq),
+                            0);
+    }
+
+    /// A tuple struct deserialized as serde's derive would.
+    #[derive(Debug, PartialEq)]
+    struct Pair(i32, i32);
+
+    impl de::Deserialize for Pair {
+        fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<Pair, D::Error>
+            where D: de::Deserializer
+        {
+            struct PairVisitor;
+
+            impl de::Visitor for PairVisitor {
+                type Value = Pair;
+
+                fn visit_seq<V>(&mut self, mut visitor: V) -> ::std::result::Result<Pair, V::Error>
+                    where V: de::SeqVisitor
+                {
+                    let a = match try!(visitor.visit()) {
+                        Some(a) => a,
+                        None => {
+                            try!(visitor.end());
+                            return Err(de::Error::invalid_length(0));
+                        }
+                    };
+                    let b = match try!(visitor.visit()) {
+                        Some(b) => b,
+                        None => {
+                            try!(visitor.end());
+                            return Err(de::Error::invalid_length(1));
+                        }
+                    };
+                    try!(visitor.end());
+                    Ok(Pair(a, b))
+                }
+            }
+
+            deserializer.deserialize_tuple_struct("Pair", 2, PairVisitor)
+        }
+    }
+
+    fn wrong_length(expected: usize, found: usize) -> ErrorCode {
+        ErrorCode::TupleStructLength {
+            name: "Pair",
+            expected: expected,
+            found: found,
+        }
+    }
+
+    #[test]
+    fn test_deserialize_tuple_struct_length() {
+        assert_eq!(from_slice::<Pair>(b"li1ei2ee").unwrap(), Pair(1, 2));
+        assert_eq!(from_slice::<Vec<Pair>>(b"lli1ei2eeli3ei4eee").unwrap(),
+                   vec![Pair(1, 2), Pair(3, 4)]);
+
+        assert_syntax_error(from_slice::<Pair>(b"li1ee"), wrong_length(2, 1), 4);
+        assert_syntax_error(from_slice::<Pair>(b"le"), wrong_length(2, 0), 1);
+        assert_syntax_error(from_slice::<Pair>(b"li1ei2ei3ee"), wrong_length(2, 3), 7);
+        assert_syntax_error(from_slice::<Pair>(b"li1ei2ei3eli4eee"), wrong_length(2, 4), 7);
+        assert_syntax_error(from_slice::<Pair>(b"i1e"),
+                            ErrorCode::InvalidType(::serde::de::Type::I64),
+                            0);
+        assert_eq!(from_slice::<Pair>(b"li1ee").unwrap_err().to_string(),
+                   "At position 4: Expected a list of 2 elements for Pair, found 1");
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Peer {
+        id: String,
+        ip: String,
+        port: u16,
+    }
+
+    enum PeerField {
+        Id,
+        Ip,
+        Port,
+        Ignore,
+    }
+
+    /// Reads a field name the way derived field visitors do, from either a str or raw bytes.
+    impl de::Deserialize for PeerField {
+        fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<PeerField, D::Error>
+            where D: de::Deserializer
+        {
+            struct PeerFieldVisitor;
+
+            impl de::Visitor for PeerFieldVisitor {
+                type Value = PeerField;
+
+                fn visit_str<E>(&mut self, value: &str) -> ::std::result::Result<PeerField, E>
+                    where E: de::Error
+                {
+                    self.visit_bytes(value.as_bytes())
+                }
+
+                fn visit_bytes<E>(&mut self, value: &[u8]) -> ::std::result::Result<PeerField, E>
+                    where E: de::Error
+                {
+                    Ok(match value {
+                        b"id" => PeerField::Id,
+                        b"ip" => PeerField::Ip,
+                        b"port" => PeerField::Port,
+                        _ => PeerField::Ignore,
+                    })
+                }
+            }
+
+            deserializer.deserialize_struct_field(PeerFieldVisitor)
+        }
+    }
+
+    impl de::Deserialize for Peer {
+        fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<Peer, D::Error>
+            where D: de::Deserializer
+        {
+            struct PeerVisitor;
+
+            impl de::Visitor for PeerVisitor {
+                type Value = Peer;
+
+                fn visit_map<V>(&mut self, mut visitor: V) -> ::std::result::Result<Peer, V::Error>
+                    where V: de::MapVisitor
+                {
+                    let mut id = None;
+                    let mut ip = None;
+                    let mut port = None;
+                    while let Some(key) = try!(visitor.visit_key()) {
+                        match key {
+                            PeerField::Id => id = Some(try!(visitor.visit_value())),
+                            PeerField::Ip => ip = Some(try!(visitor.visit_value())),
+                            PeerField::Port => port = Some(try!(visitor.visit_value())),
+                            PeerField::Ignore => {
+                                try!(visitor.visit_value::<de::impls::IgnoredAny>());
+                            }
+                        }
+                    }
+                    try!(visitor.end());
+                    Ok(Peer {
+                        id: try!(id.map_or_else(|| visitor.missing_field("id"), Ok)),
+                        ip: try!(ip.map_or_else(|| visitor.missing_field("ip"), Ok)),
+                        port: try!(port.map_or_else(|| visitor.missing_field("port"), Ok)),
+                    })
+                }
+            }
+
+            const FIELDS: &[&str] = &["id", "ip", "port"];
+            deserializer.deserialize_struct("Peer", FIELDS, PeerVisitor)
+        }
+    }
+
+    #[test]
+    fn test_deserialize_struct_any_field_order() {
+        let expected = Peer {
+            id: "abc".to_string(),
+            ip: "::1".to_string(),
+            port: 6881,
+        };
+        let entries: [&[u8]; 3] = [b"2:id3:abc", b"2:ip3:::1", b"4:porti6881e"];
+        let orders = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+        for order in &orders {
+            let mut input = b"d".to_vec();
+            for &i in order {
+                input.extend_from_slice(entries[i]);
+            }
+            input.push(b'e');
+            assert_eq!(from_slice::<Peer>(&input).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_struct_unknown_keys() {
+        // Unknown keys are skipped, even when they are not valid UTF-8
+        let peer: Peer = from_slice(b"d2:\xff\x00i1e2:id3:abc2:ip3:::14:porti6881e5:extralee")
+            .unwrap();
+        assert_eq!(peer.port, 6881);
+
+        match from_slice::<Peer>(b"d2:id3:abc4:porti1ee") {
+            Err(Error::Value(de::value::Error::MissingField("ip"))) => {}
+            other => panic!("Expected a missing field error, got {:?}", other),
+        }
+        assert_syntax_error(from_slice::<Peer>(b"li1ee"),
+                            ErrorCode::InvalidType(::serde::de::Type::Seq),
+                            0);
+    }
+
+    #[test]
+    fn test_deserialize_struct_case_insensitive_fields() {
+        fn peer_from(input: &[u8], case_insensitive: bool) -> Result<Peer> {
+            let mut de = Deserializer::new(read::SliceRead::new(input))
+                .case_insensitive_fields(case_insensitive);
+            de::Deserialize::deserialize(&mut de)
+        }
+
+        let input = b"d2:ID3:abc2:Ip3:::14:PORTi6881ee";
+        let peer = peer_from(input, true).unwrap();
+        assert_eq!(peer,
+                   Peer {
+                       id: "abc".to_string(),
+                       ip: "::1".to_string(),
+                       port: 6881,
+                   });
+        // By default keys must match exactly, so these are all skipped
+        match peer_from(input, false) {
+            Err(Error::Value(de::value::Error::MissingField("id"))) => {}
+            other => panic!("Expected a missing field error, got {:?}", other),
+        }
+        // Non-ASCII bytes are never folded
+        assert!(peer_from(b"d2:id3:abc2:ip3:::15:p\xc3\x96rti1ee", true).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_struct_deny_unknown_fields() {
+        fn peer_from(input: &[u8]) -> Result<Peer> {
+            let mut de = Deserializer::new(read::SliceRead::new(input)).deny_unknown_fields(true);
+            de::Deserialize::deserialize(&mut de)
+        }
+
+        assert_eq!(peer_from(b"d2:id3:abc2:ip3:::14:porti6881ee").unwrap().port, 6881);
+        let code = ErrorCode::UnknownField {
+            field: "extra".to_string(),
+            expected: &["id", "ip", "port"],
+        };
+        assert_syntax_error(peer_from(b"d2:id3:abc5:extrai1e2:ip3:::14:porti6881ee"), code, 10);
+        let code = ErrorCode::UnknownField {
+            field: "\u{fffd}".to_string(),
+            expected: &["id", "ip", "port"],
+        };
+        assert_syntax_error(peer_from(b"d1:\xffi1ee"), code, 1);
+        assert_eq!(peer_from(b"d5:extrai1ee").unwrap_err().to_string(),
+                   "At position 1: Unknown field extra, expected one of: id, ip, port");
+
+        // Only structs are affected, not maps
+        let mut de = Deserializer::new(read::SliceRead::new(b"d5:extrai1ee"))
+            .deny_unknown_fields(true);
+        let map: BTreeMap<String, i64> = de::Deserialize::deserialize(&mut de).unwrap();
+        assert_eq!(map["extra"], 1);
+
+        // Both options together: a key matching ignoring case is known
+        let mut de = Deserializer::new(read::SliceRead::new(b"d2:Id3:abc2:IP3:::14:Porti1ee"))
+            .case_insensitive_fields(true)
+            .deny_unknown_fields(true);
+        assert_eq!(<Peer as de::Deserialize>::deserialize(&mut de).unwrap().port, 1);
+    }
+
+    /// A struct with an optional field, (de)serialized as serde's derive would.
+    #[derive(Debug, PartialEq)]
+    struct Interval {
+        min: Option<i32>,
+    }
+
+    impl ser::Serialize for Interval {
+        fn serialize<S>(&self, serializer: &mut S) -> ::std::result::Result<(), S::Error>
+            where S: ser::Serializer
+        {
+            let mut state = try!(serializer.serialize_struct("Interval", 1));
+            try!(serializer.serialize_struct_elt(&mut state, "min", self.min));
+            serializer.serialize_struct_end(state)
+        }
+    }
+
+    impl de::Deserialize for Interval {
+        fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<Interval, D::Error>
+            where D: de::Deserializer
+        {
+            struct IntervalVisitor;
+
+            impl de::Visitor for IntervalVisitor {
+                type Value = Interval;
+
+                fn visit_map<V>(&mut self,
+                                mut visitor: V)
+                                -> ::std::result::Result<Interval, V::Error>
+                    where V: de::MapVisitor
+                {
+                    let mut min = None;
+                    while let Some(key) = try!(visitor.visit_key::<String>()) {
+                        if key == "min" {
+                            min = Some(try!(visitor.visit_value()));
+                        } else {
+                            try!(visitor.visit_value::<de::impls::IgnoredAny>());
+                        }
+                    }
+                    try!(visitor.end());
+                    Ok(Interval { min: try!(min.map_or_else(|| visitor.missing_field("min"), Ok)) })
+                }
+            }
+
+            const FIELDS: &[&str] = &["min"];
+            deserializer.deserialize_struct("Interval", FIELDS, IntervalVisitor)
+        }
+    }
+
+    #[test]
+    fn test_optional_struct_field() {
+        // Present, then absent: `None` is written by leaving the key out
+        for &(input, min) in &[(&b"d3:mini30ee"[..], Some(30)), (&b"de"[..], None)] {
+            let interval = Interval { min: min };
+            assert_eq!(from_slice::<Interval>(input).unwrap(), interval);
+            assert_eq!(from_reader::<_, Interval>(input).unwrap(), interval);
+            assert_eq!(::ser::to_vec(&interval).unwrap(), input);
+        }
+        assert_eq!(from_slice::<Interval>(b"d5:otheri1ee").unwrap(), Interval { min: None });
+
+        // An empty string is a value like any other, not a way of writing `None`
+        assert_syntax_error(from_slice::<Interval>(b"d3:min0:e"),
+                            ErrorCode::InvalidType(::serde::de::Type::Str),
+                            6);
+    }
+
+    #[test]
+    fn test_check_key_order() {
+        fn strict<T: de::Deserialize>(input: &[u8]) -> Result<T> {
+            let mut de = Deserializer::new(read::SliceRead::new(input)).check_key_order(true);
+            let value = try!(de::Deserialize::deserialize(&mut de));
+            try!(de.end());
+            Ok(value)
+        }
+
+        let map = strict::<BTreeMap<String, i64>>(b"d1:ai1e2:aai2e1:bi3ee").unwrap();
+        assert_eq!(map.len(), 3);
+        // Each dict is checked on its own, and keys compare as raw bytes
+        strict::<BTreeMap<String, BTreeMap<String, i64>>>(b"d1:ad1:bi1ee1:bd1:ai2eee").unwrap();
+        strict::<BTreeMap<::serde::bytes::ByteBuf, i64>>(b"d1:Zi1e1:ai2e1:\xffi3ee").unwrap();
+
+        assert_syntax_error(strict::<BTreeMap<String, i64>>(b"d1:bi1e1:ai2ee"),
+                            ErrorCode::UnsortedKeys,
+                            7);
+        assert_syntax_error(strict::<BTreeMap<String, i64>>(b"d1:ai1e1:ai2ee"),
+                            ErrorCode::DuplicateKey,
+                            7);
+        assert_syntax_error(strict::<Peer>(b"d2:ip3:::12:id3:abc4:porti6881ee"),
+                            ErrorCode::UnsortedKeys,
+                            10);
+        assert_syntax_error(strict::<Vec<BTreeMap<String, i64>>>(b"ld1:ai1eed1:bi1e1:bi2eee"),
+                            ErrorCode::DuplicateKey,
+                            16);
+
+        // Lenient by default
+        let map = from_slice::<BTreeMap<String, i64>>(b"d1:bi1e1:ai2ee").unwrap();
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn test_malformed_input_errors_without_panicking() {
+        assert_syntax_error(from_slice::<i64>(b"\xff"),
+                            ErrorCode::UnexpectedToken("\\xff".to_string()),
+                            0);
+        assert_syntax_error(from_slice::<Vec<i64>>(b"l\xc3e"),
+                            ErrorCode::UnexpectedToken("\\xc3".to_string()),
+                            1);
+        assert_syntax_error(from_slice::<i64>(b"i-:e"),
+                            ErrorCode::UnexpectedToken(":".to_string()),
+                            2);
+        assert_syntax_error(from_slice::<i64>(b"i99999999999999999999e"),
+                            ErrorCode::IntegerOverflow,
+                            1);
+        assert_syntax_error(from_slice::<String>(b"99999999999999999999:spam"),
+                            ErrorCode::IntegerOverflow,
+                            0);
+    }
+
+    /// Accepts a string however the deserializer chooses to present it.
+    struct AnyBytes(Vec<u8>);
+
+    impl de::Deserialize for AnyBytes {
+        fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<AnyBytes, D::Error>
+            where D: de::Deserializer
+        {
+            struct AnyBytesVisitor;
+
+            impl de::Visitor for AnyBytesVisitor {
+                type Value = AnyBytes;
+
+                fn visit_str<E>(&mut self, v: &str) -> ::std::result::Result<AnyBytes, E>
+                    where E: de::Error
+                {
+                    Ok(AnyBytes(v.as_bytes().to_vec()))
+                }
+
+                fn visit_bytes<E>(&mut self, v: &[u8]) -> ::std::result::Result<AnyBytes, E>
+                    where E: de::Error
+                {
+                    Ok(AnyBytes(v.to_vec()))
+                }
+
+                fn visit_byte_buf<E>(&mut self, v: Vec<u8>) -> ::std::result::Result<AnyBytes, E>
+                    where E: de::Error
+                {
+                    Ok(AnyBytes(v))
+                }
+            }
+
+            deserializer.deserialize(AnyBytesVisitor)
+        }
+    }
+
+    #[test]
+    fn test_deserialize_non_utf8_string() {
+        use serde::bytes::ByteBuf;
+
+        let bytes: Vec<u8> = from_slice::<ByteBuf>(b"2:\xff\x00").unwrap().into();
+        assert_eq!(bytes, vec![0xff, 0x00]);
+        assert_eq!(from_slice::<AnyBytes>(b"2:\xff\x00").unwrap().0, vec![0xff, 0x00]);
+        assert_eq!(from_slice::<AnyBytes>(b"4:spam").unwrap().0, b"spam");
+
+        match from_slice::<String>(b"2:\xff\x00") {
+            Err(Error::Utf8(_)) => {}
+            other => panic!("Expected a UTF-8 error, got {:?}", other),
+        }
+    }
+
+    /// Records which visitor method a string was handed to.
+    #[derive(Debug, PartialEq)]
+    enum StringVisit {
+        Str,
+        String,
+        Bytes,
+        ByteBuf,
+    }
+
+    impl de::Deserialize for StringVisit {
+        fn deserialize<D>(deserializer: &mut D) -> ::std::result::Result<StringVisit, D::Error>
+            where D: de::Deserializer
+        {
+            struct StringVisitVisitor;
+
+            impl de::Visitor for StringVisitVisitor {
+                type Value = StringVisit;
+
+                fn visit_str<E>(&mut self, _: &str) -> ::std::result::Result<StringVisit, E>
+                    where E: de::Error
+                {
+                    Ok(StringVisit::Str)
+                }
+
+                fn visit_string<E>(&mut self, _: String) -> ::std::result::Result<StringVisit, E>
+                    where E: de::Error
+                {
+                    Ok(StringVisit::String)
+                }
+
+                fn visit_bytes<E>(&mut self, _: &[u8]) -> ::std::result::Result<StringVisit, E>
+                    where E: de::Error
+                {
+                    Ok(StringVisit::Bytes)
+                }
+
+                fn visit_byte_buf<E>(&mut self,
+                                     _: Vec<u8>)
+                                     -> ::std::result::Result<StringVisit, E>
+                    where E: de::Error
+                {
+                    Ok(StringVisit::ByteBuf)
+                }
+            }
+
+            deserializer.deserialize(StringVisitVisitor)
+        }
+    }
+
+    #[test]
+    fn test_slice_strings_are_not_copied() {
+        assert_eq!(from_slice::<StringVisit>(b"4:spam").unwrap(), StringVisit::Str);
+        assert_eq!(from_slice::<StringVisit>(b"2:\xff\x00").unwrap(),
+                   StringVisit::Bytes);
+        assert_eq!(from_reader::<_, StringVisit>(&b"4:spam"[..]).unwrap(),
+                   StringVisit::String);
+        assert_eq!(from_reader::<_, StringVisit>(&b"2:\xff\x00"[..]).unwrap(),
+                   StringVisit::ByteBuf);
+
+        assert_syntax_error(from_slice::<StringVisit>(b"5:spam"), ErrorCode::UnexpectedEOF, 6);
+        assert_syntax_error(from_reader::<_, StringVisit>(&b"5:spam"[..]),
+                            ErrorCode::UnexpectedEOF,
+                            6);
+    }
+
+    #[test]
+    fn test_from_str() {
+        let input = "d3:cow3:mooe".to_string();
+        let map: BTreeMap<String, String> = from_str(&input).unwrap();
+        assert_eq!(map["cow"], "moo");
+        assert_eq!(from_str::<i64>("i42e").unwrap(), 42);
+        assert_syntax_error(from_str::<i64>("i42ex"), ErrorCode::UnexpectedTrailingChars, 4);
+    }
+
+    #[test]
+    fn test_from_slice_partial() {
+        let (value, rest) = from_slice_partial::<Vec<i64>>(b"li1ei2eetrailing").unwrap();
+        assert_eq!(value, vec![1, 2]);
+        assert_eq!(rest, b"trailing");
+        let (value, rest) = from_slice_partial::<String>(b"4:spam").unwrap();
+        assert_eq!(value, "spam");
+        assert!(rest.is_empty());
+        assert_syntax_error(from_slice_partial::<Vec<i64>>(b"li1e"), ErrorCode::UnexpectedEOF, 4);
+    }
+
+    #[test]
+    fn test_from_slice_partial_extended_message_frame() {
+        // A BEP 9 metadata piece: length, message id 20, extended id 3, a dict, then the payload
+        let payload = [0xab; 100];
+        let dict = b"d8:msg_typei1e5:piecei0e10:total_sizei100ee";
+        let len = (2 + dict.len() + payload.len()) as u32;
+        let mut frame = vec![(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+        frame.extend_from_slice(&[20, 3]);
+        frame.extend_from_slice(dict);
+        frame.extend_from_slice(&payload);
+
+        let (value, rest) = from_slice_partial::<::value::Value>(&frame[6..]).unwrap();
+        assert_eq!(value.get_path(&[b"piece"]), Some(::value::Value::Int(0)));
+        assert_eq!(value.get_path(&[b"total_size"]), Some(::value::Value::Int(100)));
+        assert_eq!(rest, &payload[..]);
+        // The same frame is rejected whole
+        assert_syntax_error(from_slice::<::value::Value>(&frame[6..]),
+                            ErrorCode::UnexpectedTrailingChars,
+                            dict.len());
+    }
+
+    #[test]
+    fn test_from_slice_partial_extended_handshake() {
+        use value::Value;
+
+        // A BEP 10 handshake: message id 20, extended id 0, then the dict alone
+        let dict: &[u8] = b"d1:md11:ut_metadatai3e6:ut_pexi1ee13:metadata_sizei31235e\
+                            1:pi6881e4:reqqi250e1:v11:Example 1.0e";
+        let len = (2 + dict.len()) as u32;
+        let mut frame = vec![(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+        frame.extend_from_slice(&[20, 0]);
+        frame.extend_from_slice(dict);
+
+        let (value, rest) = from_slice_partial::<Value>(&frame[6..]).unwrap();
+        assert!(rest.is_empty());
+        let m = value.get("m").unwrap().as_dict().unwrap();
+        assert_eq!(m.len(), 2);
+        assert_eq!(m.get(&b"ut_metadata"[..]), Some(&Value::Int(3)));
+        assert_eq!(m.get(&b"ut_pex"[..]), Some(&Value::Int(1)));
+        assert_eq!(value.get_path(&[b"metadata_size"]), Some(Value::Int(31235)));
+        assert_eq!(value.get_path(&[b"p"]), Some(Value::Int(6881)));
+        assert_eq!(value.get_path(&[b"reqq"]), Some(Value::Int(250)));
+        assert_eq!(value.get_path(&[b"v"]),
+                   Some(Value::ByteString(b"Example 1.0".to_vec())));
+    }
+
+    #[test]
+    fn test_deserialize_legacy_bytes() {
+        use serde::bytes::ByteBuf;
+
+        fn decode(input: &[u8]) -> Result<Vec<u8>> {
+            let mut de = Deserializer::new(read::SliceRead::new(input)).legacy_bytes(true);
+            let buf: ByteBuf = try!(de::Deserialize::deserialize(&mut de));
+            try!(de.end());
+            Ok(buf.into())
+        }
+
+        assert_eq!(decode(b"3:\x01\x02\xff").unwrap(), vec![1, 2, 0xff]);
+        assert_eq!(decode(b"li1ei2ei255ee").unwrap(), vec![1, 2, 0xff]);
+        assert_eq!(decode(b"le").unwrap(), vec![]);
+        assert_syntax_error(decode(b"li1ei256ee"),
+                            ErrorCode::IntegerOutOfRange {
+                                value: 256,
+                                target: "u8",
+                            },
+                            4);
+        assert_syntax_error(decode(b"i1e"), ErrorCode::InvalidType(de::Type::I64), 0);
+
+        // Without the flag, only the byte string form is accepted
+        assert_syntax_error(from_slice::<ByteBuf>(b"li1ei2ee"),
+                            ErrorCode::InvalidType(de::Type::Seq),
+                            0);
+    }
+
+    #[test]
+    fn test_u64_max_in_containers() {
+        use ser::to_vec;
+
+        let mut map = BTreeMap::new();
+        map.insert("downloaded".to_string(), vec![u64::max_value(), 0]);
+        let encoded = to_vec(&map).unwrap();
+        assert_eq!(encoded, b"d10:downloadedli18446744073709551615ei0eee".to_vec());
+        assert_eq!(from_slice::<BTreeMap<String, Vec<u64>>>(&encoded).unwrap(), map);
+        assert_eq!(from_reader::<_, BTreeMap<String, Vec<u64>>>(&encoded[..]).unwrap(), map);
+    }
+
+    #[test]
+    fn test_deserialize_128_bit_integers() {
+        fn i128_from(input: &[u8]) -> Result<i128> {
+            let mut de = Deserializer::new(read::SliceRead::new(input));
+            let value = try!(de.deserialize_i128());
+            try!(de.end());
+            Ok(value)
+        }
+        fn u128_from(input: &[u8]) -> Result<u128> {
+            let mut de = Deserializer::new(read::SliceRead::new(input));
+            let value = try!(de.deserialize_u128());
+            try!(de.end());
+            Ok(value)
+        }
+
+        assert_eq!(i128_from(b"i9223372036854775808e").unwrap(),
+                   i64::max_value() as i128 + 1);
+        assert_eq!(i128_from(b"i-18446744073709551616e").unwrap(),
+                   -(u64::max_value() as i128) - 1);
+        assert_eq!(i128_from(b"i170141183460469231731687303715884105727e").unwrap(),
+                   i128::max_value());
+        assert_eq!(i128_from(b"i-170141183460469231731687303715884105728e").unwrap(),
+                   i128::min_value());
+        assert_eq!(i128_from(b"i0e").unwrap(), 0);
+        assert_syntax_error(i128_from(b"i170141183460469231731687303715884105728e"),
+                            ErrorCode::IntegerOverflow,
+                            1);
+        assert_syntax_error(i128_from(b"i-170141183460469231731687303715884105729e"),
+                            ErrorCode::IntegerOverflow,
+                            2);
+
+        assert_eq!(u128_from(b"i340282366920938463463374607431768211455e").unwrap(),
+                   u128::max_value());
+        assert_syntax_error(u128_from(b"i340282366920938463463374607431768211456e"),
+                            ErrorCode::IntegerOverflow,
+                            1);
+        assert_syntax_error(u128_from(b"i-340282366920938463463374607431768211455e"),
+                            ErrorCode::IntegerOverflow,
+                            2);
+        assert_syntax_error(u128_from(b"i-1e"),
+                            ErrorCode::IntegerOutOfRange {
+                                value: -1,
+                                target: "u128",
+                            },
+                            0);
+
+        assert_syntax_error(i128_from(b"4:spam"),
+                            ErrorCode::InvalidType(de::Type::Bytes),
+                            0);
+        assert_syntax_error(u128_from(b"i-0e"), unexpected("0"), 2);
+        // Other integer types keep their `u64`-sized accumulator
+        assert_syntax_error(from_slice::<i64>(b"i18446744073709551616e"),
+                            ErrorCode::IntegerOverflow,
+                            1);
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        // One past `u64::MAX` no longer fits in the accumulator
+        assert_syntax_error(from_slice::<i64>(b"i18446744073709551616e"),
+                            ErrorCode::IntegerOverflow,
+                            1);
+        assert_syntax_error(from_slice::<i64>(b"i-99999999999999999999e"),
+                            ErrorCode::IntegerOverflow,
+                            2);
+        assert_syntax_error(from_slice::<Vec<i64>>(b"li1ei99999999999999999999ee"),
+                            ErrorCode::IntegerOverflow,
+                            5);
+        assert_syntax_error(from_slice::<de::impls::IgnoredAny>(b"i99999999999999999999e"),
+                            ErrorCode::IntegerOverflow,
+                            1);
+
+        // Anything that fits is read exactly, rather than wrapping
+        assert_eq!(from_slice::<i64>(b"i-9223372036854775808e").unwrap(), i64::min_value());
+        assert_eq!(from_slice::<u64>(b"i18446744073709551615e").unwrap(), u64::max_value());
+        assert!(from_slice::<i64>(b"i18446744073709551615e").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_bool() {
+        assert!(!from_slice::<bool>(b"i0e").unwrap());
+        assert!(from_slice::<bool>(b"i1e").unwrap());
+        assert_eq!(from_slice::<Vec<bool>>(b"li1ei0ee").unwrap(), vec![true, false]);
+        for &(input, value) in &[(&b"i2e"[..], 2), (&b"i-1e"[..], -1)] {
+            let code = ErrorCode::IntegerOutOfRange {
+                value: value,
+                target: "bool",
+            };
+            assert_syntax_error(from_slice::<bool>(input), code, 0);
+        }
+        assert_syntax_error(from_slice::<bool>(b"4:true"),
+                            ErrorCode::InvalidType(de::Type::Bytes),
+                            0);
+    }
+
+    #[test]
+    fn test_leading_garbage() {
+        let bom = ErrorCode::LeadingGarbage("a byte order mark");
+        assert_syntax_error(from_slice::<i64>(b"\xef\xbb\xbfi1e"), bom.clone(), 0);
+        assert_syntax_error(from_slice::<Vec<i64>>(b"\xef\xbb\xbfli1ee"), bom.clone(), 0);
+        assert_syntax_error(from_slice::<BTreeMap<String, i64>>(b"\xef\xbb\xbfde"), bom, 0);
+
+        // A lone EF, or one not followed by the rest of the mark, is just an unexpected byte
+        assert_syntax_error(from_slice::<i64>(b"\xef"), unexpected("\\xef"), 0);
+        assert_syntax_error(from_slice::<i64>(b"\xefi1e"), unexpected("\\xef"), 0);
+        assert_syntax_error(from_slice::<i64>(b"\xef\xbbi1e"), unexpected("\\xef"), 0);
+
+        let whitespace = ErrorCode::LeadingGarbage("whitespace");
+        assert_syntax_error(from_slice::<i64>(b" i1e"), whitespace.clone(), 0);
+        assert_syntax_error(from_slice::<String>(b"\n4:spam"), whitespace.clone(), 0);
+        assert_syntax_error(from_slice::<de::impls::IgnoredAny>(b"\tle"), whitespace, 0);
+
+        let err = from_slice::<i64>(b"\xef\xbb\xbfi1e").unwrap_err();
+        assert_eq!(format!("{}", err),
+                   "At position 0: Input does not start with a bencode value; found a byte order \
+                    mark");
+
+        // Past the start of the input these are just unexpected
+        assert_syntax_error(from_slice::<Vec<i64>>(b"l i1ee"),
+                            ErrorCode::UnexpectedToken(" ".to_string()),
+                            1);
+    }
+}
@@ -9,21 +9,102 @@ use std::string::FromUtf8Error;
 use serde::de;
 use serde::ser;
 
+use super::value::Kind;
+
 /// The errors that can arise.
 #[derive(Clone, PartialEq, Debug)]
 pub enum ErrorCode {
     /// Default error code for when the parser encounters a malformed message
     UnexpectedToken(String),
+    /// Used when the input starts with something that commonly ends up in front of bencode by
+    /// mistake, such as a UTF-8 byte order mark or whitespace, naming what was found
+    LeadingGarbage(&'static str),
+    /// Used when the length prefix of a string holds something other than digits followed by a
+    /// `:`
+    InvalidLengthPrefix,
     /// Used when the deserializer hits the end of input when it's not expecting it
     UnexpectedEOF,
     /// Used when there are remaining characters after deserializing from an iterator
     UnexpectedTrailingChars,
     /// Used when the serializer cannot serialize the given type
     UnsupportedType(de::Type),
+    /// Used when the value being deserialized into does not accept the bencode type found in the
+    /// input
+    InvalidType(de::Type),
     /// Used when trying to serialize a number that cannot be bencoded
     NumberOutOfRange(u64),
+    /// Used when an integer in the input is too large or too small to be read at all: beyond
+    /// the range of `i64` and `u64` alike, or of `i64` where only signed values are accepted
+    UnrepresentableInteger(i128),
+    /// Used when the digits of an integer or string length in the input overflow a `u64`
+    IntegerOverflow,
+    /// Used when an integer in the input starts with a `+` sign, which bencode does not allow
+    PlusSign,
+    /// Used when an integer in the input does not fit in the integer type being deserialized
+    /// into
+    IntegerOutOfRange {
+        value: i128,
+        target: &'static str,
+    },
+    /// Used when a tuple struct is read from a list with a different number of elements than the
+    /// struct has fields
+    TupleStructLength {
+        name: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// Used when trying to serialize `Some(None)`, which can't be told apart from `None` once
+    /// encoded
+    NestedOption,
     /// Used when trying to serialize a non-finite number
     NonFiniteNumber(f64),
+    /// Used when the deserializer cannot allocate enough memory to hold a string of the given
+    /// declared length
+    AllocationFailed(usize),
+    /// Used when the input nests lists and dicts more deeply than the configured maximum depth
+    DepthLimitExceeded(usize),
+    /// Used when the input holds more values than the configured maximum number of nodes
+    NodeLimitExceeded(usize),
+    /// Used when a string in the input declares a length greater than the configured maximum
+    StringTooLong(usize),
+    /// Used when the length prefix of a string has more digits than the configured maximum
+    LengthPrefixTooLong(usize),
+    /// Used when a `StreamDeserializer` has yielded its maximum number of values and more input
+    /// remains
+    ValueLimitExceeded(usize),
+    /// Used by `Value::require` when a dict lacks some required keys, or holds them with values of
+    /// the wrong kind. Each mistyped key is given with the expected kind, then the kind found.
+    RequiredKeys {
+        missing: Vec<String>,
+        mistyped: Vec<(String, Kind, Kind)>,
+    },
+    /// Used when a `Value` is not of the kind it was expected to be, giving the expected kind,
+    /// then the kind found
+    UnexpectedKind {
+        expected: Kind,
+        found: Kind,
+    },
+    /// Used by `Value::expect_dict_len` when a dict holds a different number of entries than
+    /// expected
+    DictLength {
+        expected: usize,
+        found: usize,
+    },
+    /// Used when a dict key does not sort after the key before it, either in the input or when a
+    /// serializer trusting the caller's key order is given keys out of order
+    UnsortedKeys,
+    /// Used when a dict in the input repeats a key
+    DuplicateKey,
+    /// Used when a struct's dict holds a key naming none of its fields, if the deserializer denies
+    /// unknown fields. Gives the key, with any invalid UTF-8 replaced, and the fields expected.
+    UnknownField {
+        field: String,
+        expected: &'static [&'static str],
+    },
+    /// Used by `Torrent::from_bytes` when the input does not start with a dict
+    NotATorrent,
+    /// Used by the `EventWriter` when given events that do not form a valid bencode document
+    InvalidEvent(String),
     /// Catchall syntax for error messages
     Custom(String),
 }
@@ -32,16 +113,104 @@ impl fmt::Display for ErrorCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             ErrorCode::UnexpectedToken(ref tok) => write!(f, "Unexpected token {}", tok),
+            ErrorCode::LeadingGarbage(found) => {
+                write!(f, "Input does not start with a bencode value; found {}", found)
+            }
+            ErrorCode::InvalidLengthPrefix => write!(f, "Invalid string length prefix"),
             ErrorCode::UnexpectedEOF => write!(f, "Unexpected end of input"),
             ErrorCode::UnexpectedTrailingChars => write!(f, "Unexpected trailing characters"),
             ErrorCode::UnsupportedType(ref t) => write!(f, "Cannot serialize type {}", t),
+            ErrorCode::InvalidType(ref t) => {
+                match bencode_type_name(t) {
+                    Some(name) => write!(f, "Invalid type: unexpected bencode {}", name),
+                    None => write!(f, "Invalid type: unexpected {}", t),
+                }
+            }
             ErrorCode::NumberOutOfRange(ref n) => write!(f, "Number {} out of range", n),
+            ErrorCode::UnrepresentableInteger(ref n) => write!(f, "Integer {} out of range", n),
+            ErrorCode::IntegerOverflow => write!(f, "Integer too large to represent"),
+            ErrorCode::PlusSign => write!(f, "Integers may not have a leading + sign"),
+            ErrorCode::IntegerOutOfRange { value, target } => {
+                write!(f, "Value {} out of range for {}", value, target)
+            }
+            ErrorCode::TupleStructLength { name, expected, found } => {
+                write!(f, "Expected a list of {} elements for {}, found {}", expected, name, found)
+            }
+            ErrorCode::NestedOption => {
+                write!(f, "Cannot serialize a nested option: Some(None) would decode as None")
+            }
             ErrorCode::NonFiniteNumber(ref n) => write!(f, "Non-finite number encountered: {}", n),
+            ErrorCode::AllocationFailed(ref len) => {
+                write!(f, "Could not allocate {} bytes for string", len)
+            }
+            ErrorCode::DepthLimitExceeded(ref max) => {
+                write!(f, "Nesting exceeds the maximum depth of {}", max)
+            }
+            ErrorCode::NodeLimitExceeded(ref max) => {
+                write!(f, "Input holds more than the maximum of {} values", max)
+            }
+            ErrorCode::StringTooLong(ref len) => {
+                write!(f, "String of length {} exceeds the maximum length", len)
+            }
+            ErrorCode::LengthPrefixTooLong(ref max) => {
+                write!(f, "String length prefix has more than the maximum of {} digits", max)
+            }
+            ErrorCode::ValueLimitExceeded(ref max) => {
+                write!(f, "Stream holds more than the maximum of {} values", max)
+            }
+            ErrorCode::RequiredKeys { ref missing, ref mistyped } => {
+                if !missing.is_empty() {
+                    try!(write!(f, "Missing keys: {}", missing.join(", ")));
+                }
+                if !mistyped.is_empty() {
+                    if !missing.is_empty() {
+                        try!(write!(f, "; "));
+                    }
+                    try!(write!(f, "wrong type for keys: "));
+                    for (i, &(ref key, expected, found)) in mistyped.iter().enumerate() {
+                        if i > 0 {
+                            try!(write!(f, ", "));
+                        }
+                        try!(write!(f, "{} (expected {}, found {})", key, expected, found));
+                    }
+                }
+                Ok(())
+            }
+            ErrorCode::UnexpectedKind { expected, found } => {
+                write!(f, "Expected {}, found {}", expected, found)
+            }
+            ErrorCode::DictLength { expected, found } => {
+                write!(f, "Expected a dictionary of {} entries, found {}", expected, found)
+            }
+            ErrorCode::UnsortedKeys => write!(f, "Dict keys are not in sorted order"),
+            ErrorCode::DuplicateKey => write!(f, "Dict key appears more than once"),
+            ErrorCode::UnknownField { ref field, expected } => {
+                write!(f, "Unknown field {}, expected one of: {}", field, expected.join(", "))
+            }
+            ErrorCode::NotATorrent => write!(f, "Torrent file must be a bencode dictionary"),
+            ErrorCode::InvalidEvent(ref msg) => write!(f, "Invalid event: {}", msg),
             ErrorCode::Custom(ref msg) => write!(f, "{}", msg),
         }
     }
 }
 
+/// Describes a deserialization type in terms of the bencode type it is read from.
+fn bencode_type_name(t: &de::Type) -> Option<&'static str> {
+    match *t {
+        de::Type::Bool | de::Type::Usize | de::Type::U8 | de::Type::U16 | de::Type::U32 |
+        de::Type::U64 | de::Type::Isize | de::Type::I8 | de::Type::I16 | de::Type::I32 |
+        de::Type::I64 | de::Type::F32 | de::Type::F64 => Some("integer"),
+        de::Type::Char | de::Type::Str | de::Type::String | de::Type::Bytes => {
+            Some("byte string")
+        }
+        de::Type::Seq | de::Type::Tuple | de::Type::TupleStruct | de::Type::TupleVariant => {
+            Some("list")
+        }
+        de::Type::Map | de::Type::Struct | de::Type::StructVariant => Some("dictionary"),
+        _ => None,
+    }
+}
+
 /// Represents all possible errors that can occur when serializing or deserializing a value into
 /// bencode.
 #[derive(Debug)]
@@ -56,6 +225,35 @@ pub enum Error {
     Ser(ErrorCode),
 
     Value(de::value::Error),
+
+    /// Another error, with a note on what was being done when it happened. See `Error::context`.
+    Context(String, Box<Error>),
+}
+
+impl Error {
+    /// Attaches a note, such as "while parsing announce URL", to this error. The note is shown
+    /// before the original message, and `code` and `position` still see through to the original.
+    pub fn context<T: Into<String>>(self, msg: T) -> Error {
+        Error::Context(msg.into(), Box::new(self))
+    }
+
+    /// Returns the code of a syntax or serialization error.
+    pub fn code(&self) -> Option<&ErrorCode> {
+        match *self {
+            Error::Syntax(ref code, _) | Error::Ser(ref code) => Some(code),
+            Error::Context(_, ref err) => err.code(),
+            _ => None,
+        }
+    }
+
+    /// Returns the position in the input of a syntax error.
+    pub fn position(&self) -> Option<usize> {
+        match *self {
+            Error::Syntax(_, pos) => Some(pos),
+            Error::Context(_, ref err) => err.position(),
+            _ => None,
+        }
+    }
 }
 
 impl error::Error for Error {
@@ -66,6 +264,7 @@ impl error::Error for Error {
             Error::Utf8(..) => "utf-8 error",
             Error::Ser(..) => "Serialization error",
             Error::Value(..) => "Value error",
+            Error::Context(..) => "Error with context",
         }
     }
 
@@ -74,6 +273,7 @@ impl error::Error for Error {
             Error::Io(ref err) => Some(err),
             Error::Utf8(ref err) => Some(err),
             Error::Value(ref err) => Some(err),
+            Error::Context(_, ref err) => Some(&**err),
             _ => None,
         }
     }
@@ -87,6 +287,7 @@ impl fmt::Display for Error {
             Error::Utf8(ref err) => write!(fmt, "{}", err),
             Error::Value(ref err) => write!(fmt, "{}", err),
             Error::Ser(ref code) => write!(fmt, "{}", code),
+            Error::Context(ref msg, ref err) => write!(fmt, "{}: {}", msg, err),
         }
     }
 }
@@ -105,6 +306,10 @@ impl de::Error for Error {
     fn end_of_stream() -> Error {
         Error::Syntax(ErrorCode::UnexpectedEOF, 0)
     }
+
+    fn invalid_type(ty: de::Type) -> Error {
+        Error::Syntax(ErrorCode::InvalidType(ty), 0)
+    }
 }
 
 impl From<io::Error> for Error {
@@ -125,5 +330,91 @@ impl From<de::value::Error> for Error {
     }
 }
 
+/// Lets bencode errors flow through `io::Result`. An I/O error is handed back as it was, and any
+/// other error becomes an `InvalidData` error wrapping the bencode error, so its message is kept
+/// and it can still be recovered with `io::Error::into_inner`.
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(err) => err,
+            Error::Context(msg, err) => {
+                let err = io::Error::from(*err);
+                io::Error::new(err.kind(), format!("{}: {}", msg, err))
+            }
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
 /// Helper alias for `Result` objects that return a JSON `Error`.
 pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let err = Error::Syntax(ErrorCode::UnexpectedEOF, 12).context("while parsing announce URL");
+        assert_eq!(format!("{}", err),
+                   "while parsing announce URL: At position 12: Unexpected end of input");
+        assert_eq!(err.code(), Some(&ErrorCode::UnexpectedEOF));
+        assert_eq!(err.position(), Some(12));
+
+        let err = err.context("while reading torrent");
+        assert_eq!(format!("{}", err),
+                   "while reading torrent: while parsing announce URL: At position 12: Unexpected \
+                    end of input");
+        assert_eq!(err.code(), Some(&ErrorCode::UnexpectedEOF));
+        assert_eq!(err.position(), Some(12));
+    }
+
+    #[test]
+    fn test_code_and_position() {
+        let err = Error::Ser(ErrorCode::NestedOption);
+        assert_eq!(err.code(), Some(&ErrorCode::NestedOption));
+        assert_eq!(err.position(), None);
+
+        let err = Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof)).context("while saving");
+        assert_eq!(format!("{}", err), "while saving: unexpected end of file");
+        assert_eq!(err.code(), None);
+        assert_eq!(err.position(), None);
+    }
+
+    #[test]
+    fn test_into_io_error() {
+        let err = io::Error::from(Error::Syntax(ErrorCode::UnexpectedEOF, 3));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "At position 3: Unexpected end of input");
+        match err.into_inner().map(|inner| inner.downcast::<Error>()) {
+            Some(Ok(inner)) => assert_eq!(inner.code(), Some(&ErrorCode::UnexpectedEOF)),
+            other => panic!("Expected the bencode error inside, got {:?}", other),
+        }
+
+        let err = io::Error::from(Error::Ser(ErrorCode::NestedOption));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), ErrorCode::NestedOption.to_string());
+
+        let utf8 = String::from_utf8(vec![0xff]).unwrap_err();
+        let msg = utf8.to_string();
+        let err = io::Error::from(Error::Utf8(utf8));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), msg);
+
+        let err = io::Error::from(Error::Value(de::value::Error::MissingField("id")));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), de::value::Error::MissingField("id").to_string());
+
+        let err = io::Error::from(Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "gone")));
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(err.to_string(), "gone");
+
+        let err = io::Error::from(Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "gone"))
+            .context("while sending"));
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(err.to_string(), "while sending: gone");
+        let err = io::Error::from(Error::Syntax(ErrorCode::UnexpectedEOF, 3).context("reading"));
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "reading: At position 3: Unexpected end of input");
+    }
+}
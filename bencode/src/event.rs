@@ -0,0 +1,384 @@
+//! A low-level, pull-based interface for walking bencode one token at a time, without building a
+//! `Value` or deserializing into a concrete type.
+
+use std::io;
+
+use super::de::Deserializer;
+use super::error::{Error, ErrorCode, Result};
+use super::read::Read;
+use super::ser::Formatter;
+
+/// A single structural element of a bencode document.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The start of a dict (`d`). Until the matching `DictEnd`, events alternate between a
+    /// `ByteString` key and the events making up its value.
+    DictStart,
+    /// The end of a dict (`e`)
+    DictEnd,
+    /// The start of a list (`l`)
+    ListStart,
+    /// The end of a list (`e`)
+    ListEnd,
+    /// An integer, e.g. `i42e`
+    Integer(i64),
+    /// A byte string, e.g. `4:spam`
+    ByteString(Vec<u8>),
+}
+
+enum Frame {
+    List,
+    Dict { expecting_key: bool },
+}
+
+/// Reads a single bencode value as a stream of `Event`s.
+///
+/// The reader checks that the input is structurally valid as it goes: containers must be closed,
+/// dict keys must be byte strings, and nothing may follow the top-level value. Once an error has
+/// been returned the iterator yields nothing further.
+pub struct EventReader<R>
+    where R: Read
+{
+    de: Deserializer<R>,
+    stack: Vec<Frame>,
+    done: bool,
+}
+
+impl<R> EventReader<R>
+    where R: Read
+{
+    pub fn new(reader: R) -> Self {
+        EventReader::from_deserializer(Deserializer::new(reader))
+    }
+
+    /// Reads events through an already configured `Deserializer`, so that its limits apply.
+    pub(crate) fn from_deserializer(de: Deserializer<R>) -> Self {
+        EventReader {
+            de: de,
+            stack: vec![],
+            done: false,
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<Event>> {
+        const DICT_OPEN: u8 = b'd';
+        const LIST_OPEN: u8 = b'l';
+        const INT_OPEN: u8 = b'i';
+        const END: u8 = b'e';
+
+        if self.done {
+            return Ok(None);
+        }
+
+        let ch = try!(self.de.next_char());
+        let expecting_key = match self.stack.last() {
+            Some(&Frame::Dict { expecting_key }) => expecting_key,
+            _ => false,
+        };
+        if expecting_key {
+            match ch {
+                b'0'...b'9' | END => {}
+                _ => return Err(self.de.unexpected_token(ch)),
+            }
+        }
+
+        let event = match ch {
+            DICT_OPEN => {
+                try!(self.de.begin_value(true));
+                self.stack.push(Frame::Dict { expecting_key: true });
+                return Ok(Some(Event::DictStart));
+            }
+            LIST_OPEN => {
+                try!(self.de.begin_value(true));
+                self.stack.push(Frame::List);
+                return Ok(Some(Event::ListStart));
+            }
+            END => {
+                let event = match self.stack.pop() {
+                    Some(Frame::List) => Event::ListEnd,
+                    Some(Frame::Dict { expecting_key: true }) => Event::DictEnd,
+                    // Either a dict key without a value, or an `e` outside of any container
+                    _ => return Err(self.de.unexpected_token(ch)),
+                };
+                self.de.end_container();
+                event
+            }
+            INT_OPEN => {
+                try!(self.de.begin_value(false));
+                Event::Integer(try!(self.de.read_int()))
+            }
+            b'0'...b'9' => {
+                try!(self.de.begin_value(false));
+                Event::ByteString(try!(self.de.read_string(ch)))
+            }
+            _ => return Err(self.de.unexpected_token(ch)),
+        };
+
+        match self.stack.last_mut() {
+            Some(&mut Frame::Dict { ref mut expecting_key }) => *expecting_key = !*expecting_key,
+            Some(&mut Frame::List) => {}
+            None => {
+                self.done = true;
+                if try!(self.de.peek_char()).is_some() {
+                    return Err(self.de.syntax_error(ErrorCode::UnexpectedTrailingChars));
+                }
+            }
+        }
+
+        Ok(Some(event))
+    }
+}
+
+impl<R> Iterator for EventReader<R>
+    where R: Read
+{
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Result<Event>> {
+        match self.next_event() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => None,
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+enum WriteFrame {
+    List,
+    Dict {
+        expecting_key: bool,
+        prev_key: Option<Vec<u8>>,
+    },
+}
+
+/// Writes a stream of `Event`s as bencode.
+///
+/// The writer rejects any event that would produce an invalid document: unbalanced containers,
+/// dict keys that are not byte strings or are not in strictly increasing order, a dict key with no
+/// value, or anything following the top-level value. Call `finish` once all events have been
+/// written to check that the document is complete.
+pub struct EventWriter<W> {
+    writer: W,
+    formatter: Formatter,
+    stack: Vec<WriteFrame>,
+    done: bool,
+}
+
+impl<W> EventWriter<W>
+    where W: io::Write
+{
+    pub fn new(writer: W) -> Self {
+        EventWriter {
+            writer: writer,
+            formatter: Formatter::default(),
+            stack: vec![],
+            done: false,
+        }
+    }
+
+    /// Writes a single event.
+    pub fn write(&mut self, event: &Event) -> Result<()> {
+        if self.done {
+            return Err(invalid_event("event after the end of the top-level value"));
+        }
+
+        if let Some(&mut WriteFrame::Dict { expecting_key: true, ref mut prev_key }) =
+               self.stack.last_mut() {
+            match *event {
+                Event::ByteString(ref key) => {
+                    if let Some(ref prev) = *prev_key {
+                        if key <= prev {
+                            return Err(invalid_event("dict keys must be sorted and unique"));
+                        }
+                    }
+                    *prev_key = Some(key.clone());
+                }
+                Event::DictEnd => {}
+                _ => return Err(invalid_event("dict keys must be byte strings")),
+            }
+        }
+
+        match *event {
+            Event::DictStart => {
+                try!(self.formatter.dict_open(&mut self.writer));
+                self.stack.push(WriteFrame::Dict {
+                    expecting_key: true,
+                    prev_key: None,
+                });
+                return Ok(());
+            }
+            Event::ListStart => {
+                try!(self.formatter.list_open(&mut self.writer));
+                self.stack.push(WriteFrame::List);
+                return Ok(());
+            }
+            Event::DictEnd => {
+                match self.stack.pop() {
+                    Some(WriteFrame::Dict { expecting_key: true, .. }) => {}
+                    Some(WriteFrame::Dict { expecting_key: false, .. }) => {
+                        return Err(invalid_event("dict key without a value"))
+                    }
+                    _ => return Err(invalid_event("DictEnd without a matching DictStart")),
+                }
+                try!(self.formatter.dict_close(&mut self.writer));
+            }
+            Event::ListEnd => {
+                match self.stack.pop() {
+                    Some(WriteFrame::List) => {}
+                    _ => return Err(invalid_event("ListEnd without a matching ListStart")),
+                }
+                try!(self.formatter.list_close(&mut self.writer));
+            }
+            Event::Integer(i) => try!(self.formatter.int(&mut self.writer, i)),
+            Event::ByteString(ref bytes) => try!(self.formatter.bytes(&mut self.writer, bytes)),
+        }
+
+        match self.stack.last_mut() {
+            Some(&mut WriteFrame::Dict { ref mut expecting_key, .. }) => {
+                *expecting_key = !*expecting_key
+            }
+            Some(&mut WriteFrame::List) => {}
+            None => self.done = true,
+        }
+        Ok(())
+    }
+
+    /// Checks that a complete value has been written and returns the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        if !self.done {
+            return Err(invalid_event("incomplete document"));
+        }
+        Ok(self.writer)
+    }
+}
+
+fn invalid_event(msg: &str) -> Error {
+    Error::Ser(ErrorCode::InvalidEvent(msg.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use read::SliceRead;
+
+    fn events(input: &[u8]) -> Result<Vec<Event>> {
+        EventReader::new(SliceRead::new(input)).collect()
+    }
+
+    #[test]
+    fn test_scalar_events() {
+        assert_eq!(events(b"i-42e").unwrap(), vec![Event::Integer(-42)]);
+        assert_eq!(events(b"4:spam").unwrap(),
+                   vec![Event::ByteString(b"spam".to_vec())]);
+        assert_eq!(events(b"0:").unwrap(), vec![Event::ByteString(vec![])]);
+    }
+
+    #[test]
+    fn test_nested_events() {
+        assert_eq!(events(b"ld3:bar4:spam3:fooi1eei-2eleded2:\xff\xfeli3eeee").unwrap(),
+                   vec![Event::ListStart,
+                        Event::DictStart,
+                        Event::ByteString(b"bar".to_vec()),
+                        Event::ByteString(b"spam".to_vec()),
+                        Event::ByteString(b"foo".to_vec()),
+                        Event::Integer(1),
+                        Event::DictEnd,
+                        Event::Integer(-2),
+                        Event::ListStart,
+                        Event::ListEnd,
+                        Event::DictStart,
+                        Event::DictEnd,
+                        Event::DictStart,
+                        Event::ByteString(vec![0xff, 0xfe]),
+                        Event::ListStart,
+                        Event::Integer(3),
+                        Event::ListEnd,
+                        Event::DictEnd,
+                        Event::ListEnd]);
+    }
+
+    #[test]
+    fn test_non_string_key() {
+        assert!(events(b"di1ei2ee").is_err());
+    }
+
+    #[test]
+    fn test_key_without_value() {
+        assert!(events(b"d3:fooe").is_err());
+    }
+
+    #[test]
+    fn test_unbalanced_input() {
+        assert!(events(b"li1e").is_err());
+        assert!(events(b"e").is_err());
+        assert!(events(b"lei1e").is_err());
+    }
+
+    fn write_events(events: &[Event]) -> Result<Vec<u8>> {
+        let mut writer = EventWriter::new(Vec::new());
+        for event in events {
+            try!(writer.write(event));
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn test_reader_writer_round_trip() {
+        let input: &[u8] = b"ld3:bar4:spam3:fooi1eei-2eleded2:\xff\xfeli3eeee";
+        let events = events(input).unwrap();
+        assert_eq!(write_events(&events).unwrap(), input);
+    }
+
+    #[test]
+    fn test_write_unsorted_keys() {
+        let result = write_events(&[Event::DictStart,
+                                    Event::ByteString(b"b".to_vec()),
+                                    Event::Integer(1),
+                                    Event::ByteString(b"a".to_vec()),
+                                    Event::Integer(2),
+                                    Event::DictEnd]);
+        assert!(result.is_err());
+
+        let result = write_events(&[Event::DictStart,
+                                    Event::ByteString(b"a".to_vec()),
+                                    Event::Integer(1),
+                                    Event::ByteString(b"a".to_vec()),
+                                    Event::Integer(2),
+                                    Event::DictEnd]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_write_non_string_key() {
+        let result = write_events(&[Event::DictStart, Event::Integer(1), Event::Integer(2)]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_write_mismatched_end() {
+        assert!(write_events(&[Event::ListStart, Event::DictEnd]).is_err());
+        assert!(write_events(&[Event::DictStart, Event::ListEnd]).is_err());
+        assert!(write_events(&[Event::DictStart,
+                               Event::ByteString(b"a".to_vec()),
+                               Event::DictEnd])
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_incomplete_or_trailing() {
+        assert!(write_events(&[Event::ListStart]).is_err());
+        assert!(write_events(&[]).is_err());
+        assert!(write_events(&[Event::Integer(1), Event::Integer(2)]).is_err());
+    }
+
+    #[test]
+    fn test_stops_after_error() {
+        let mut reader = EventReader::new(SliceRead::new(b"lxe"));
+        assert_eq!(reader.next().unwrap().unwrap(), Event::ListStart);
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,260 @@
+//! `io::Write` adapters for measuring or hashing encoded output without buffering it, and for
+//! bounding how much of it is written.
+
+use std::io::{self, Write};
+
+/// Wraps a writer, passing every write through to it while keeping a count of the bytes written.
+pub struct CountingWriter<W> {
+    writer: W,
+    count: usize,
+}
+
+impl<W> CountingWriter<W>
+    where W: Write
+{
+    pub fn new(writer: W) -> Self {
+        CountingWriter {
+            writer: writer,
+            count: 0,
+        }
+    }
+
+    /// The number of bytes successfully written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W> Write for CountingWriter<W>
+    where W: Write
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = try!(self.writer.write(buf));
+        self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A writer that discards everything written to it, only counting the bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizeWriter {
+    count: usize,
+}
+
+impl SizeWriter {
+    pub fn new() -> Self {
+        SizeWriter::default()
+    }
+
+    /// The number of bytes written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl Write for SizeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer that discards everything written to it, keeping a 64-bit FNV-1a hash of the bytes.
+/// FNV-1a is fixed by its specification, so the hash of the same bytes never changes between
+/// runs, platforms or releases, unlike `std`'s `DefaultHasher`. It is not a cryptographic hash.
+#[derive(Clone, Copy, Debug)]
+pub struct HashWriter {
+    hash: u64,
+}
+
+impl HashWriter {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    pub fn new() -> Self {
+        HashWriter { hash: HashWriter::OFFSET_BASIS }
+    }
+
+    /// The hash of the bytes written so far.
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl Default for HashWriter {
+    fn default() -> Self {
+        HashWriter::new()
+    }
+}
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            self.hash = (self.hash ^ b as u64).wrapping_mul(HashWriter::PRIME);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Wraps a writer, failing any write that would take the total written past `limit` bytes. As
+/// much of the write as fits is passed through first, so the output is cut off at exactly the
+/// limit.
+pub struct LimitWriter<W> {
+    writer: W,
+    limit: usize,
+    count: usize,
+}
+
+impl<W> LimitWriter<W>
+    where W: Write
+{
+    pub fn new(writer: W, limit: usize) -> Self {
+        LimitWriter {
+            writer: writer,
+            limit: limit,
+            count: 0,
+        }
+    }
+
+    /// The number of bytes successfully written so far.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W> Write for LimitWriter<W>
+    where W: Write
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let remaining = self.limit - self.count;
+        if remaining == 0 && !buf.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                      format!("output exceeds the limit of {} bytes",
+                                              self.limit)));
+        }
+        let n = buf.len().min(remaining);
+        let written = try!(self.writer.write(&buf[..n]));
+        self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer that accepts at most `limit` bytes per call, to exercise partial writes.
+    struct ShortWriter {
+        data: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.limit);
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_counting_writer() {
+        let mut w = CountingWriter::new(Vec::new());
+        w.write_all(b"d3:cow").unwrap();
+        assert_eq!(w.count(), 6);
+        w.write_all(b"3:moo").unwrap();
+        w.write_all(b"").unwrap();
+        w.write_all(b"e").unwrap();
+        assert_eq!(w.count(), 12);
+        assert_eq!(w.get_ref().len(), 12);
+        assert_eq!(w.into_inner(), b"d3:cow3:mooe");
+    }
+
+    #[test]
+    fn test_counting_writer_partial_writes() {
+        let mut w = CountingWriter::new(ShortWriter {
+            data: vec![],
+            limit: 2,
+        });
+        assert_eq!(w.write(b"spam").unwrap(), 2);
+        assert_eq!(w.count(), 2);
+        w.write_all(b"eggs").unwrap();
+        assert_eq!(w.count(), 6);
+        assert_eq!(w.into_inner().data, b"speggs");
+    }
+
+    #[test]
+    fn test_size_writer() {
+        let mut w = SizeWriter::new();
+        assert_eq!(w.count(), 0);
+        w.write_all(b"l4:spam").unwrap();
+        w.write_all(&[0; 1000]).unwrap();
+        w.write_all(b"e").unwrap();
+        assert_eq!(w.count(), 1008);
+    }
+
+    #[test]
+    fn test_limit_writer() {
+        let mut w = LimitWriter::new(Vec::new(), 8);
+        w.write_all(b"l4:spam").unwrap();
+        assert_eq!(w.count(), 7);
+        assert!(w.write_all(b"i1ee").is_err());
+        assert_eq!(w.count(), 8);
+        w.write_all(b"").unwrap();
+        assert_eq!(w.into_inner(), b"l4:spami");
+    }
+
+    #[test]
+    fn test_hash_writer() {
+        // Reference values from the FNV-1a specification
+        assert_eq!(HashWriter::new().finish(), 0xcbf29ce484222325);
+        let mut w = HashWriter::new();
+        w.write_all(b"a").unwrap();
+        assert_eq!(w.finish(), 0xaf63dc4c8601ec8c);
+        let mut w = HashWriter::new();
+        w.write_all(b"foo").unwrap();
+        assert_eq!(w.finish(), 0xdcb27518fed9d577);
+
+        // Splitting the input across writes does not change the hash
+        let mut w = HashWriter::new();
+        w.write_all(b"f").unwrap();
+        w.write_all(b"oo").unwrap();
+        assert_eq!(w.finish(), 0xdcb27518fed9d577);
+    }
+}
@@ -6,15 +6,40 @@
 //!
 //! > Coming soon!
 //!
+//! `encode` and `decode` are provided as aliases for `to_vec` and `from_slice`, matching the
+//! naming used by other bencode libraries.
+//!
 
 #[macro_use]
 extern crate serde;
 extern crate itoa;
+#[cfg(feature = "sha1")]
+extern crate sha1;
+#[cfg(feature = "erased")]
+extern crate erased_serde;
 
 pub mod error;
 pub mod read;
 pub mod ser;
 pub mod de;
+pub mod value;
+pub mod timestamp;
+pub mod byte_array;
+pub mod compact_peers;
+pub mod raw;
+pub mod sorted_set;
+pub mod event;
+pub mod io;
+#[cfg(feature = "torrent")]
+pub mod torrent;
 
-pub use ser::{to_writer, to_vec, to_string};
-pub use de::{from_reader, from_slice, from_string};
+pub use ser::{to_writer, to_vec, to_vec_with_capacity, to_string, encode, encoded_len,
+              VariantEncoding};
+pub use de::{from_reader, from_slice, from_slice_partial, from_str, from_string,
+                 decode};
+pub use value::Value;
+pub use raw::RawValue;
+#[cfg(feature = "sha1")]
+pub use raw::info_hash;
+#[cfg(feature = "erased")]
+pub use ser::to_vec_list_erased;
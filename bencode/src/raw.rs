@@ -0,0 +1,220 @@
+//! Helpers for keeping a value as the exact bencoded bytes it was read from, rather than decoding
+//! it. This is mostly useful for a torrent's `info` dict, whose SHA1 hash must be computed over
+//! the bytes as they appeared in the file:
+//!
+//! ```rust,ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Torrent {
+//!     announce: String,
+//!     #[serde(serialize_with = "serde_bencode::raw::serialize",
+//!             deserialize_with = "serde_bencode::raw::deserialize")]
+//!     info: Vec<u8>,
+//! }
+//! ```
+//!
+//! The `RawValue` type does the same without the attributes, so the field above could simply be
+//! declared as `info: RawValue`.
+//!
+//! On serialization the bytes are written out verbatim, after checking that they hold a single
+//! well-formed bencode value. Both helpers only work with this crate's `Serializer` and
+//! `Deserializer`; with other formats the value is handled as a plain byte string.
+
+use serde::bytes::{ByteBufVisitor, Bytes};
+use serde::de;
+use serde::ser;
+
+use super::de::from_slice;
+use super::error;
+#[cfg(feature = "sha1")]
+use super::value::LazyValue;
+
+/// The newtype struct name through which the helpers signal the serializer and deserializer.
+pub(crate) const TOKEN: &str = "$serde_bencode::RawBencode";
+
+/// Writes `bytes`, which must hold a single bencoded value, to the output as-is.
+pub fn serialize<S>(bytes: &[u8], serializer: &mut S) -> Result<(), S::Error>
+    where S: ser::Serializer
+{
+    serializer.serialize_newtype_struct(TOKEN, Bytes::new(bytes))
+}
+
+/// Reads the next value from the input without decoding it, returning its encoded bytes.
+pub fn deserialize<D>(deserializer: &mut D) -> Result<Vec<u8>, D::Error>
+    where D: de::Deserializer
+{
+    deserializer.deserialize_newtype_struct(TOKEN, ByteBufVisitor).map(Into::into)
+}
+
+/// A value kept as the exact bencoded bytes it was read from. When deserializing from a slice or
+/// string, these are copied straight out of the input.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RawValue {
+    bytes: Vec<u8>,
+}
+
+impl RawValue {
+    /// The encoded bytes of this value.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Consumes this value, returning its encoded bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Decodes this value into `T`.
+    pub fn decode<T: de::Deserialize>(&self) -> error::Result<T> {
+        from_slice(&self.bytes)
+    }
+}
+
+impl ser::Serialize for RawValue {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: ser::Serializer
+    {
+        serialize(&self.bytes, serializer)
+    }
+}
+
+impl de::Deserialize for RawValue {
+    fn deserialize<D>(deserializer: &mut D) -> Result<RawValue, D::Error>
+        where D: de::Deserializer
+    {
+        deserialize(deserializer).map(|bytes| RawValue { bytes: bytes })
+    }
+}
+
+/// Computes the info-hash of a metainfo (`.torrent`) file: the SHA1 of its `info` dict, taken
+/// over the bytes exactly as they appear in `bytes`. Hashing a re-encoded dict instead would give
+/// a different hash for files whose keys are not sorted.
+#[cfg(feature = "sha1")]
+pub fn info_hash(bytes: &[u8]) -> error::Result<[u8; 20]> {
+    let entries = try!(LazyValue::new(bytes).entries());
+    match entries.get(&b"info"[..]) {
+        Some(info) => Ok(sha1::Sha1::from(info.as_bytes()).digest().bytes()),
+        None => Err(error::Error::Value(de::value::Error::MissingField("info"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::{de, ser};
+
+    use de::from_slice;
+    use ser::to_vec;
+
+    #[derive(Debug, PartialEq)]
+    struct Raw(Vec<u8>);
+
+    impl ser::Serialize for Raw {
+        fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+            where S: ser::Serializer
+        {
+            super::serialize(&self.0, serializer)
+        }
+    }
+
+    impl de::Deserialize for Raw {
+        fn deserialize<D>(deserializer: &mut D) -> Result<Raw, D::Error>
+            where D: de::Deserializer
+        {
+            super::deserialize(deserializer).map(Raw)
+        }
+    }
+
+    const TORRENT: &[u8] = b"d8:announce12:http://a.b/c4:infod6:lengthi-7e4:name4:spam\
+                             6:piecesl0:2:xyleeee";
+
+    #[cfg(feature = "sha1")]
+    #[test]
+    fn test_info_hash() {
+        let torrent = include_bytes!("../fixtures/example.torrent");
+        // The fixture's info dict has unsorted keys, so hashing a re-encoded copy would not match
+        assert_eq!(super::info_hash(torrent).unwrap(),
+                   [0xeb, 0x7f, 0xf6, 0x1f, 0x92, 0x33, 0x3c, 0x42, 0xea, 0x7d, 0x66, 0x2d, 0x9a,
+                    0xac, 0xb6, 0xb9, 0x92, 0x32, 0x98, 0xc6]);
+    }
+
+    #[cfg(feature = "sha1")]
+    #[test]
+    fn test_info_hash_errors() {
+        assert!(super::info_hash(b"d8:announce3:urle").is_err());
+        assert!(super::info_hash(b"d4:infod").is_err());
+        assert!(super::info_hash(b"li1ee").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let map: BTreeMap<String, Raw> = from_slice(TORRENT).unwrap();
+        assert_eq!(map["announce"], Raw(b"12:http://a.b/c".to_vec()));
+        assert_eq!(map["info"],
+                   Raw(b"d6:lengthi-7e4:name4:spam6:piecesl0:2:xyleee".to_vec()));
+        assert_eq!(to_vec(&map).unwrap(), TORRENT);
+    }
+
+    #[test]
+    fn test_raw_value() {
+        use super::RawValue;
+
+        // Dict keys out of order are kept as they were rather than re-sorted
+        let input: &[u8] = b"d8:announce12:http://a.b/c4:infod4:name4:spam6:lengthi7eee";
+        let info_start = 32;
+        let maps: [BTreeMap<String, RawValue>; 2] = [from_slice(input).unwrap(),
+                                                     ::de::from_reader(input).unwrap()];
+        for map in &maps {
+            assert_eq!(map["info"].as_bytes(), &input[info_start..input.len() - 1]);
+            assert_eq!(map["announce"].decode::<String>().unwrap(), "http://a.b/c");
+            assert_eq!(to_vec(&map).unwrap(), input);
+        }
+
+        let info: RawValue = from_slice(b"d6:lengthi-7ee").unwrap();
+        let map: BTreeMap<String, i64> = info.decode().unwrap();
+        assert_eq!(map["length"], -7);
+        assert_eq!(info.into_bytes(), b"d6:lengthi-7ee");
+        assert!(from_slice::<RawValue>(b"d6:lengthi-07ee").is_err());
+
+        // Values copied out of a slice and values rebuilt from a reader are checked alike
+        let invalid: [&[u8]; 5] =
+            [b"di1ei2ee", b"d1:ae", b"d1:ai1ei2ee", b"ld1:aee", b"dli1eei1ee"];
+        for input in &invalid {
+            let from_slice = from_slice::<RawValue>(input).unwrap_err();
+            let from_reader = ::de::from_reader::<_, RawValue>(*input).unwrap_err();
+            assert_eq!(format!("{:?}", from_slice), format!("{:?}", from_reader));
+        }
+        fn strict<R: ::read::Read>(read: R) -> String {
+            let mut de = ::de::Deserializer::new(read).check_key_order(true);
+            format!("{:?}", <RawValue as de::Deserialize>::deserialize(&mut de).unwrap_err())
+        }
+        for input in &[&b"d1:bi1e1:ai2ee"[..], b"d1:ai1e1:ai2ee"] {
+            let iter = input.iter().map(|&b| Ok(b));
+            assert_eq!(strict(::read::SliceRead::new(input)),
+                       strict(::read::IteratorRead::new(iter)));
+        }
+    }
+
+    #[test]
+    fn test_scalars() {
+        assert_eq!(from_slice::<Raw>(b"i0e").unwrap(), Raw(b"i0e".to_vec()));
+        assert_eq!(from_slice::<Raw>(b"0:").unwrap(), Raw(b"0:".to_vec()));
+        assert_eq!(from_slice::<Vec<Raw>>(b"li1e1:ae").unwrap(),
+                   vec![Raw(b"i1e".to_vec()), Raw(b"1:a".to_vec())]);
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        assert!(from_slice::<Raw>(b"d1:ai1e").is_err());
+        assert!(from_slice::<Raw>(b"e").is_err());
+        assert!(from_slice::<Raw>(b"i01e").is_err());
+    }
+
+    #[test]
+    fn test_serialize_rejects_invalid_bytes() {
+        assert!(to_vec(&Raw(b"li1e".to_vec())).is_err());
+        assert!(to_vec(&Raw(b"i1ei2e".to_vec())).is_err());
+        assert!(to_vec(&Raw(vec![])).is_err());
+        assert_eq!(to_vec(&vec![Raw(b"de".to_vec())]).unwrap(), b"ldee");
+    }
+}
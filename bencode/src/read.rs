@@ -1,20 +1,50 @@
 use std::io;
 
-use serde::iter;
-
 use super::error::Result;
 
 pub trait Read {
     fn next_char(&mut self) -> Option<Result<u8>>;
-    fn peek_char(&self) -> Option<u8>;
+    /// Returns the next byte without consuming it. Readers backed by I/O may need to read ahead
+    /// to answer, but never past the byte returned. A read error is returned as it happens, not
+    /// mistaken for the end of the input.
+    fn peek_char(&mut self) -> Option<Result<u8>>;
+    /// The number of bytes consumed so far, i.e. the offset of the next byte to be read.
     fn position(&self) -> usize;
+
+    /// Consumes up to `n` bytes without looking at them, returning how many were skipped. Fewer
+    /// than `n` bytes are skipped only if the input ends first.
+    fn skip(&mut self, n: usize) -> Result<usize> {
+        for skipped in 0..n {
+            match self.next_char() {
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err),
+                None => return Ok(skipped),
+            }
+        }
+        Ok(n)
+    }
+
+    /// Consumes up to `len` bytes and lends them out without copying, for readers that hold the
+    /// whole input in memory. Fewer than `len` bytes are returned only if the input ends first.
+    /// Readers that cannot lend out their input return `None`, and the bytes are then read one at
+    /// a time instead.
+    fn read_slice(&mut self, _len: usize) -> Option<Result<&[u8]>> {
+        None
+    }
+
+    /// The whole input, for readers that hold it in memory, so that the bytes between two
+    /// positions can be lent out. Other readers return `None`.
+    fn source(&self) -> Option<&[u8]> {
+        None
+    }
 }
 
 pub struct IteratorRead<I>
     where I: Iterator<Item = io::Result<u8>>
 {
-    iter: iter::LineColIterator<I>,
-    ch: Option<u8>,
+    iter: I,
+    peeked: Option<io::Result<u8>>,
+    pos: usize,
 }
 
 impl<I> IteratorRead<I>
@@ -22,8 +52,9 @@ impl<I> IteratorRead<I>
 {
     pub fn new(raw_iter: I) -> Self {
         IteratorRead {
-            iter: iter::LineColIterator::new(raw_iter),
-            ch: None,
+            iter: raw_iter,
+            peeked: None,
+            pos: 0,
         }
     }
 }
@@ -32,22 +63,85 @@ impl<I> Read for IteratorRead<I>
     where I: Iterator<Item = io::Result<u8>>
 {
     fn next_char(&mut self) -> Option<Result<u8>> {
-        match self.iter.next() {
-            Some(Ok(t)) => {
-                self.ch = Some(t);
-                Some(Ok(t))
+        let next = match self.peeked.take() {
+            Some(next) => Some(next),
+            None => self.iter.next(),
+        };
+        match next {
+            Some(Ok(ch)) => {
+                self.pos += 1;
+                Some(Ok(ch))
             }
-            Some(err_res) => Some(err_res.map_err(From::from)),
-            _ => None,
+            Some(Err(err)) => Some(Err(From::from(err))),
+            None => None,
+        }
+    }
+
+    /// A read error is handed out once, by whichever of `peek_char` and `next_char` comes first.
+    fn peek_char(&mut self) -> Option<Result<u8>> {
+        if self.peeked.is_none() {
+            self.peeked = self.iter.next();
+        }
+        match self.peeked {
+            Some(Ok(ch)) => Some(Ok(ch)),
+            Some(Err(_)) => self.peeked.take().map(|next| next.map_err(From::from)),
+            None => None,
+        }
+    }
+
+    fn position(&self) -> usize {
+        self.pos
+    }
+}
+
+/// Reads from an `io::BufRead` one byte at a time, peeking into its buffer rather than reading
+/// ahead, so that no byte past the end of a value is ever consumed. Whatever follows the value
+/// stays in the underlying reader, which `into_inner` hands back.
+pub struct IoRead<R>
+    where R: io::BufRead
+{
+    reader: R,
+    pos: usize,
+}
+
+impl<R> IoRead<R>
+    where R: io::BufRead
+{
+    pub fn new(reader: R) -> Self {
+        IoRead {
+            reader: reader,
+            pos: 0,
         }
     }
 
-    fn peek_char(&self) -> Option<u8> {
-        self.ch
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> Read for IoRead<R>
+    where R: io::BufRead
+{
+    fn next_char(&mut self) -> Option<Result<u8>> {
+        let ch = match self.reader.fill_buf() {
+            Ok(&[]) => return None,
+            Ok(buf) => buf[0],
+            Err(err) => return Some(Err(From::from(err))),
+        };
+        self.reader.consume(1);
+        self.pos += 1;
+        Some(Ok(ch))
+    }
+
+    fn peek_char(&mut self) -> Option<Result<u8>> {
+        match self.reader.fill_buf() {
+            Ok(buf) => buf.first().map(|&ch| Ok(ch)),
+            Err(err) => Some(Err(From::from(err))),
+        }
     }
 
     fn position(&self) -> usize {
-        self.iter.col()
+        self.pos
     }
 }
 
@@ -67,7 +161,7 @@ impl<'a> SliceRead<'a> {
 
 impl<'a> Read for SliceRead<'a> {
     fn next_char(&mut self) -> Option<Result<u8>> {
-        if let Some(ch) = self.peek_char() {
+        if let Some(Ok(ch)) = self.peek_char() {
             self.pos += 1;
             Some(Ok(ch))
         } else {
@@ -75,16 +169,32 @@ impl<'a> Read for SliceRead<'a> {
         }
     }
 
-    fn peek_char(&self) -> Option<u8> {
+    fn peek_char(&mut self) -> Option<Result<u8>> {
         if self.pos == self.slice.len() {
             return None;
         }
-        Some(self.slice[self.pos])
+        Some(Ok(self.slice[self.pos]))
     }
 
     fn position(&self) -> usize {
         self.pos
     }
+
+    fn skip(&mut self, n: usize) -> Result<usize> {
+        let skipped = n.min(self.slice.len() - self.pos);
+        self.pos += skipped;
+        Ok(skipped)
+    }
+
+    fn read_slice(&mut self, len: usize) -> Option<Result<&[u8]>> {
+        let start = self.pos;
+        self.pos += len.min(self.slice.len() - start);
+        Some(Ok(&self.slice[start..self.pos]))
+    }
+
+    fn source(&self) -> Option<&[u8]> {
+        Some(self.slice)
+    }
 }
 
 pub struct StringRead<'a> {
@@ -102,11 +212,23 @@ impl<'a> Read for StringRead<'a> {
         self.slice_read.next_char()
     }
 
-    fn peek_char(&self) -> Option<u8> {
+    fn peek_char(&mut self) -> Option<Result<u8>> {
         self.slice_read.peek_char()
     }
 
     fn position(&self) -> usize {
         self.slice_read.position()
     }
+
+    fn skip(&mut self, n: usize) -> Result<usize> {
+        self.slice_read.skip(n)
+    }
+
+    fn read_slice(&mut self, len: usize) -> Option<Result<&[u8]>> {
+        self.slice_read.read_slice(len)
+    }
+
+    fn source(&self) -> Option<&[u8]> {
+        self.slice_read.source()
+    }
 }
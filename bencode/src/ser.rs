@@ -1,12 +1,15 @@
 use std::collections::BTreeMap;
 use std::io;
-use std::str::FromStr;
 
 use itoa;
 use serde::de::Type;
 use serde::ser;
 
 use super::error::{Error, ErrorCode, Result};
+use super::event::EventReader;
+use super::io::SizeWriter;
+use super::raw;
+use super::read::SliceRead;
 
 macro_rules! bencode_int {
     ($w:expr, $i:expr) => {{
@@ -20,6 +23,11 @@ macro_rules! bencode_int {
 pub struct Serializer<W> {
     writer: W,
     formatter: Formatter,
+    /// Set while serializing the contents of a `raw` newtype, whose bytes are written verbatim.
+    raw: bool,
+    /// Set while serializing the contents of a `Some`, until a list or dict is started, so that a
+    /// `None` directly inside it can be caught.
+    in_some: bool,
 }
 
 impl<W> Serializer<W>
@@ -29,9 +37,66 @@ impl<W> Serializer<W>
     pub fn new(writer: W) -> Self {
         Serializer {
             writer: writer,
-            formatter: Formatter,
+            formatter: Formatter::default(),
+            raw: false,
+            in_some: false,
         }
     }
+
+    /// When set, maps are written out entry by entry as they are serialized, instead of being
+    /// buffered and sorted before anything is written. This saves memory for large maps, but the
+    /// caller must serialize keys in sorted order (e.g. from a `BTreeMap`): a key that does not
+    /// sort after the one before it fails with `ErrorCode::UnsortedKeys`, leaving the output cut
+    /// short. Each value is still encoded before its key is written, so that an entry whose value
+    /// is `None` can be left out. Structs are always buffered, since their fields are serialized
+    /// in declaration order.
+    pub fn trust_key_order(mut self, trust: bool) -> Self {
+        self.formatter.trust_key_order = trust;
+        self
+    }
+
+    /// When set, `true` and `false` are written as `i1e` and `i0e`, as used for flags such as a
+    /// torrent's `private` key. By default serializing a `bool` fails, since bencode has no
+    /// boolean type and reading the integer back as a `bool` is a convention, not part of the
+    /// format.
+    pub fn bool_as_int(mut self, bool_as_int: bool) -> Self {
+        self.formatter.bool_as_int = bool_as_int;
+        self
+    }
+
+    /// When set, floats are truncated to integers, so `3.9` is written as `i3e` and `-0.9` as
+    /// `i0e`. By default serializing a float fails, since bencode has no float type and the
+    /// truncation loses data. NaN and the infinities fail either way.
+    pub fn truncate_floats(mut self, truncate_floats: bool) -> Self {
+        self.formatter.truncate_floats = truncate_floats;
+        self
+    }
+
+    /// When set, a struct field whose value encodes as an empty list or dict (`le` or `de`) is
+    /// left out of the struct's dict, as some torrent producers do to save bytes. Readers can get
+    /// the field back with `#[serde(default)]`. Maps are not affected.
+    pub fn omit_empty(mut self, omit_empty: bool) -> Self {
+        self.formatter.omit_empty = omit_empty;
+        self
+    }
+
+    /// Sets how enum variants holding data are encoded. The default is `VariantEncoding::Dict`.
+    pub fn variant_encoding(mut self, encoding: VariantEncoding) -> Self {
+        self.formatter.variant_encoding = encoding;
+        self
+    }
+
+    /// Serializes a value into a new buffer using the same options as this serializer.
+    fn encode_nested<T: ser::Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        let mut ser = Serializer {
+            writer: Vec::with_capacity(128),
+            formatter: self.formatter,
+            raw: false,
+            in_some: false,
+        };
+        try!(value.serialize(&mut ser));
+        Ok(ser.writer)
+    }
 }
 
 impl<W> ser::Serializer for Serializer<W>
@@ -46,8 +111,12 @@ impl<W> ser::Serializer for Serializer<W>
     type StructState = DictEncoder;
     type StructVariantState = DictEncoder;
 
+    /// Bencode has no boolean type, so this fails unless `bool_as_int` is set.
     #[inline]
-    fn serialize_bool(&mut self, _: bool) -> Result<()> {
+    fn serialize_bool(&mut self, v: bool) -> Result<()> {
+        if self.formatter.bool_as_int {
+            return self.formatter.int(&mut self.writer, v as i64);
+        }
         Err(Error::Ser(ErrorCode::UnsupportedType(Type::Bool)))
     }
 
@@ -98,19 +167,26 @@ impl<W> ser::Serializer for Serializer<W>
 
     #[inline]
     fn serialize_u64(&mut self, v: u64) -> Result<()> {
-        if v > i64::max_value() as u64 {
-            return Err(Error::Ser(ErrorCode::NumberOutOfRange(v)));
-        }
         bencode_int!(&mut self.writer, v)
     }
 
+    /// Bencode has no floats, so this fails unless `truncate_floats` is set.
     #[inline]
     fn serialize_f32(&mut self, v: f32) -> Result<()> {
+        if !self.formatter.truncate_floats {
+            return Err(Error::Ser(ErrorCode::UnsupportedType(Type::F32)));
+        }
         self.serialize_f64(v as f64)
     }
 
+    /// Bencode has no floats, so this fails unless `truncate_floats` is set, in which case they
+    /// are truncated to integers. NaN and the infinities have no integer to truncate to, and are
+    /// rejected rather than written as whatever the cast yields.
     #[inline]
     fn serialize_f64(&mut self, v: f64) -> Result<()> {
+        if !self.formatter.truncate_floats {
+            return Err(Error::Ser(ErrorCode::UnsupportedType(Type::F64)));
+        }
         if v.is_finite() {
             bencode_int!(&mut self.writer, v as i64)
         } else {
@@ -128,24 +204,31 @@ impl<W> ser::Serializer for Serializer<W>
         self.formatter.string(&mut self.writer, v)
     }
 
+    /// Bytes are written as a length-prefixed byte string, e.g. `3:\x01\x02\x03`. Note that serde
+    /// hands a plain `Vec<u8>` or `&[u8]` to `serialize_seq` instead, giving a list of integers;
+    /// wrap them in `serde::bytes::Bytes` or `ByteBuf` to get here.
     #[inline]
     fn serialize_bytes(&mut self, v: &[u8]) -> Result<()> {
-        let mut state = try!(self.serialize_seq(Some(v.len())));
-        for byte in v {
-            try!(self.serialize_seq_elt(&mut state, byte));
+        if self.raw {
+            // Make sure we never write out something that isn't a single bencode value
+            for event in EventReader::new(SliceRead::new(v)) {
+                try!(event);
+            }
+            return self.writer.write_all(v).map_err(From::from);
         }
-        self.serialize_seq_end(state)
+        self.formatter.bytes(&mut self.writer, v)
     }
 
+    /// Bencode has no null type, so `()` is encoded as an empty dict, the same as a unit struct.
     #[inline]
     fn serialize_unit(&mut self) -> Result<()> {
-        Ok(())
+        try!(self.formatter.dict_open(&mut self.writer));
+        self.formatter.dict_close(&mut self.writer)
     }
 
     #[inline]
     fn serialize_unit_struct(&mut self, _name: &'static str) -> Result<()> {
-        try!(self.formatter.dict_open(&mut self.writer));
-        self.formatter.dict_close(&mut self.writer)
+        self.serialize_unit()
     }
 
     #[inline]
@@ -159,9 +242,15 @@ impl<W> ser::Serializer for Serializer<W>
 
     #[inline]
     fn serialize_newtype_struct<T: ser::Serialize>(&mut self,
-                                                   _name: &'static str,
+                                                   name: &'static str,
                                                    value: T)
                                                    -> Result<()> {
+        if name == raw::TOKEN {
+            self.raw = true;
+            let result = value.serialize(self);
+            self.raw = false;
+            return result;
+        }
         value.serialize(self)
     }
 
@@ -172,24 +261,34 @@ impl<W> ser::Serializer for Serializer<W>
                                                     variant: &'static str,
                                                     value: T)
                                                     -> Result<()> {
-        try!(self.formatter.dict_open(&mut self.writer));
+        self.in_some = false;
+        try!(self.formatter.variant_open(&mut self.writer));
         try!(self.serialize_str(variant));
         try!(value.serialize(self));
-        self.formatter.dict_close(&mut self.writer)
+        self.formatter.variant_close(&mut self.writer)
     }
 
+    /// `None` is written as nothing at all, which leaves struct fields and map entries out.
+    /// `Some(None)` would be written the same way and read back as `None`, so it is an error.
     #[inline]
     fn serialize_none(&mut self) -> Result<()> {
-        self.serialize_unit()
+        if self.in_some {
+            return Err(Error::Ser(ErrorCode::NestedOption));
+        }
+        Ok(())
     }
 
     #[inline]
     fn serialize_some<T: ser::Serialize>(&mut self, value: T) -> Result<()> {
-        value.serialize(self)
+        self.in_some = true;
+        let result = value.serialize(self);
+        self.in_some = false;
+        result
     }
 
     #[inline]
     fn serialize_seq(&mut self, len: Option<usize>) -> Result<State> {
+        self.in_some = false;
         try!(self.formatter.list_open(&mut self.writer));
         if len == Some(0) {
             try!(self.formatter.list_close(&mut self.writer));
@@ -260,7 +359,7 @@ impl<W> ser::Serializer for Serializer<W>
                                variant: &'static str,
                                len: usize)
                                -> Result<State> {
-        try!(self.formatter.dict_open(&mut self.writer));
+        try!(self.formatter.variant_open(&mut self.writer));
         try!(self.serialize_str(variant));
         self.serialize_seq(Some(len))
     }
@@ -276,11 +375,23 @@ impl<W> ser::Serializer for Serializer<W>
     #[inline]
     fn serialize_tuple_variant_end(&mut self, state: State) -> Result<()> {
         try!(self.serialize_seq_end(state));
-        self.formatter.dict_close(&mut self.writer)
+        self.formatter.variant_close(&mut self.writer)
     }
 
+    /// Bencode requires dict keys to be sorted, so by default every entry of a map is buffered
+    /// until the end of the map, whether or not its length is known up front. Entries are then
+    /// written in the canonical order, comparing the raw bytes of the keys, so a shorter key
+    /// comes before any longer key it is a prefix of, regardless of how the encoded length
+    /// prefixes would sort. The output is thus the same however the map iterates, so a `HashMap`
+    /// always encodes to the same bytes. See `trust_key_order` to write entries out as they
+    /// arrive instead.
     #[inline]
     fn serialize_map(&mut self, _len: Option<usize>) -> Result<DictEncoder> {
+        self.in_some = false;
+        if self.formatter.trust_key_order {
+            try!(self.formatter.dict_open(&mut self.writer));
+            return Ok(DictEncoder::streaming());
+        }
         Ok(DictEncoder::new())
     }
 
@@ -289,7 +400,17 @@ impl<W> ser::Serializer for Serializer<W>
                                             state: &mut DictEncoder,
                                             key: T)
                                             -> Result<()> {
-        let sub_ser = try!(to_string(&key));
+        if state.streaming {
+            let key = try!(self.encode_nested(&key));
+            if let Some(ref prev) = state.prev_key {
+                if raw_key(&key) <= raw_key(prev) {
+                    return Err(Error::Ser(ErrorCode::UnsortedKeys));
+                }
+            }
+            state.add_key(key);
+            return Ok(());
+        }
+        let sub_ser = try!(self.encode_nested(&key));
         Ok((*state).add_key(sub_ser))
     }
 
@@ -298,18 +419,33 @@ impl<W> ser::Serializer for Serializer<W>
                                               state: &mut DictEncoder,
                                               value: T)
                                               -> Result<()> {
-        let sub_ser = try!(to_string(&value));
+        if state.streaming {
+            // The key is only written once the value is known not to be `None`
+            let value = try!(self.encode_nested(&value));
+            if !value.is_empty() {
+                if let Some(ref key) = state.prev_key {
+                    try!(self.writer.write_all(key));
+                }
+                try!(self.writer.write_all(&value));
+            }
+            return Ok(());
+        }
+        let sub_ser = try!(self.encode_nested(&value));
         Ok((*state).add_value(sub_ser))
     }
 
     #[inline]
     fn serialize_map_end(&mut self, state: DictEncoder) -> Result<()> {
+        if state.streaming {
+            return self.formatter.dict_close(&mut self.writer);
+        }
         state.finalize_encode(self)
     }
 
     #[inline]
-    fn serialize_struct(&mut self, _name: &'static str, len: usize) -> Result<DictEncoder> {
-        self.serialize_map(Some(len))
+    fn serialize_struct(&mut self, _name: &'static str, _len: usize) -> Result<DictEncoder> {
+        self.in_some = false;
+        Ok(DictEncoder::new())
     }
 
     #[inline]
@@ -319,6 +455,14 @@ impl<W> ser::Serializer for Serializer<W>
                                                value: V)
                                                -> Result<()> {
         try!(self.serialize_map_key(state, key));
+        if self.formatter.omit_empty {
+            let value = try!(self.encode_nested(&value));
+            if value == b"le" || value == b"de" {
+                return Ok(());
+            }
+            state.add_value(value);
+            return Ok(());
+        }
         self.serialize_map_value(state, value)
     }
 
@@ -334,9 +478,9 @@ impl<W> ser::Serializer for Serializer<W>
                                 variant: &'static str,
                                 len: usize)
                                 -> Result<DictEncoder> {
-        try!(self.formatter.dict_open(&mut self.writer));
+        try!(self.formatter.variant_open(&mut self.writer));
         try!(self.serialize_str(variant));
-        self.serialize_map(Some(len))
+        self.serialize_struct(variant, len)
     }
 
     #[inline]
@@ -351,14 +495,17 @@ impl<W> ser::Serializer for Serializer<W>
     #[inline]
     fn serialize_struct_variant_end(&mut self, state: DictEncoder) -> Result<()> {
         try!(self.serialize_struct_end(state));
-        self.formatter.dict_close(&mut self.writer)
+        self.formatter.variant_close(&mut self.writer)
     }
 }
 
+/// Collects the entries of a map or struct as already encoded bytes, keyed by the encoded key,
+/// so that they can be written out in sorted order. Nothing is required to be valid UTF-8.
 #[doc(hidden)]
 pub struct DictEncoder {
-    data: BTreeMap<String, String>,
-    prev_key: Option<String>,
+    data: BTreeMap<Vec<u8>, Vec<u8>>,
+    prev_key: Option<Vec<u8>>,
+    streaming: bool,
 }
 
 impl DictEncoder {
@@ -366,32 +513,56 @@ impl DictEncoder {
         DictEncoder {
             data: BTreeMap::new(),
             prev_key: None,
+            streaming: false,
         }
     }
 
-    fn add_key(&mut self, key: String) {
+    /// An encoder whose entries are written straight to the serializer's writer, so nothing is
+    /// buffered here.
+    fn streaming() -> Self {
+        DictEncoder { streaming: true, ..DictEncoder::new() }
+    }
+
+    fn add_key(&mut self, key: Vec<u8>) {
         self.prev_key = Some(key);
     }
 
-    fn add_value(&mut self, value: String) {
+    /// Every bencode value encodes to at least one byte, so an empty value can only come from
+    /// `None`, which is represented by leaving the entry out altogether.
+    fn add_value(&mut self, value: Vec<u8>) {
+        if value.is_empty() {
+            return;
+        }
         if let Some(ref key) = self.prev_key {
-            self.data.insert(String::from_str(key).unwrap(), value);
+            self.data.insert(key.clone(), value);
         }
     }
 
     fn finalize_encode<W>(&self, s: &mut Serializer<W>) -> Result<()>
         where W: io::Write
     {
+        // Keys are stored in their encoded `<len>:<key>` form, which doesn't sort the same way as
+        // the raw keys that bencode orders by (`1:t` would come before `2:id`).
+        let mut entries: Vec<_> = self.data.iter().collect();
+        entries.sort_by(|&(a, _), &(b, _)| raw_key(a).cmp(raw_key(b)));
+
         try!(s.formatter.dict_open(&mut s.writer));
-        for (k, v) in &self.data {
-            try!(write!(s.writer, "{}", k));
-            try!(write!(s.writer, "{}", v));
+        for (k, v) in entries {
+            try!(s.writer.write_all(k));
+            try!(s.writer.write_all(v));
         }
         try!(s.formatter.dict_close(&mut s.writer));
         Ok(())
     }
 }
 
+fn raw_key(encoded: &[u8]) -> &[u8] {
+    match encoded.iter().position(|&b| b == b':') {
+        Some(i) => &encoded[i + 1..],
+        None => encoded,
+    }
+}
+
 #[doc(hidden)]
 #[derive(Eq, PartialEq)]
 pub enum State {
@@ -400,39 +571,90 @@ pub enum State {
     Rest,
 }
 
-#[derive(Debug)]
-struct Formatter;
+/// How an enum variant that holds data is encoded. Unit variants are always encoded as just the
+/// variant name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VariantEncoding {
+    /// A single-entry dict from the variant name to its value, e.g. `d7:Varianti1ee`
+    #[default]
+    Dict,
+    /// A two-element list of the variant name and its value, e.g. `l7:Varianti1ee`
+    List,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Formatter {
+    trust_key_order: bool,
+    bool_as_int: bool,
+    truncate_floats: bool,
+    omit_empty: bool,
+    variant_encoding: VariantEncoding,
+}
 
 impl Formatter {
-    fn string<W>(&self, w: &mut W, s: &str) -> Result<()>
+    pub(crate) fn int<W, I>(&self, w: &mut W, i: I) -> Result<()>
+        where W: io::Write,
+              I: itoa::Integer
+    {
+        bencode_int!(w, i)
+    }
+
+    pub(crate) fn string<W>(&self, w: &mut W, s: &str) -> Result<()>
         where W: io::Write
     {
         write!(w, "{}:{}", s.len(), s).map_err(From::from)
     }
 
-    fn dict_open<W>(&self, w: &mut W) -> Result<()>
+    /// Writes the length prefix and then the bytes as they are, without any UTF-8 checks. To bound
+    /// the output, wrap the writer in an `io::LimitWriter`, which stops a large payload part way.
+    pub(crate) fn bytes<W>(&self, w: &mut W, b: &[u8]) -> Result<()>
+        where W: io::Write
+    {
+        try!(write!(w, "{}:", b.len()));
+        w.write_all(b).map_err(From::from)
+    }
+
+    pub(crate) fn dict_open<W>(&self, w: &mut W) -> Result<()>
         where W: io::Write
     {
         write!(w, "d").map_err(From::from)
     }
 
-    fn dict_close<W>(&self, w: &mut W) -> Result<()>
+    pub(crate) fn dict_close<W>(&self, w: &mut W) -> Result<()>
         where W: io::Write
     {
         write!(w, "e").map_err(From::from)
     }
 
-    fn list_open<W>(&self, w: &mut W) -> Result<()>
+    pub(crate) fn list_open<W>(&self, w: &mut W) -> Result<()>
         where W: io::Write
     {
         write!(w, "l").map_err(From::from)
     }
 
-    fn list_close<W>(&self, w: &mut W) -> Result<()>
+    pub(crate) fn list_close<W>(&self, w: &mut W) -> Result<()>
         where W: io::Write
     {
         write!(w, "e").map_err(From::from)
     }
+
+    pub(crate) fn variant_open<W>(&self, w: &mut W) -> Result<()>
+        where W: io::Write
+    {
+        match self.variant_encoding {
+            VariantEncoding::Dict => self.dict_open(w),
+            VariantEncoding::List => self.list_open(w),
+        }
+    }
+
+    pub(crate) fn variant_close<W>(&self, w: &mut W) -> Result<()>
+        where W: io::Write
+    {
+        match self.variant_encoding {
+            VariantEncoding::Dict => self.dict_close(w),
+            VariantEncoding::List => self.list_close(w),
+        }
+    }
 }
 
 pub fn to_writer<W: ?Sized + io::Write, T: ser::Serialize>(writer: &mut W,
@@ -444,7 +666,13 @@ pub fn to_writer<W: ?Sized + io::Write, T: ser::Serialize>(writer: &mut W,
 }
 
 pub fn to_vec<T: ser::Serialize>(value: &T) -> Result<Vec<u8>> {
-    let mut writer = Vec::with_capacity(128);
+    to_vec_with_capacity(value, 128)
+}
+
+/// Like `to_vec`, but starts with a buffer of `capacity` bytes. If the output fits, the buffer
+/// is never reallocated; `encoded_len` gives the exact size when it is worth computing upfront.
+pub fn to_vec_with_capacity<T: ser::Serialize>(value: &T, capacity: usize) -> Result<Vec<u8>> {
+    let mut writer = Vec::with_capacity(capacity);
     try!(to_writer(&mut writer, value));
     Ok(writer)
 }
@@ -454,6 +682,30 @@ pub fn to_string<T: ser::Serialize>(value: &T) -> Result<String> {
     String::from_utf8(vec).map_err(From::from)
 }
 
+/// Serializes values of different types, assembled at runtime, as a single list.
+#[cfg(feature = "erased")]
+pub fn to_vec_list_erased(items: &[&dyn erased_serde::Serialize]) -> Result<Vec<u8>> {
+    to_vec(&items)
+}
+
+/// Computes the number of bytes `value` encodes to, without keeping the encoded output.
+pub fn encoded_len<T: ser::Serialize>(value: &T) -> Result<usize> {
+    let mut writer = SizeWriter::new();
+    try!(to_writer(&mut writer, value));
+    Ok(writer.count())
+}
+
+/// An alias for `to_vec`, for those used to the `encode`/`decode` naming of other bencode
+/// libraries.
+///
+/// ```rust
+/// let encoded = serde_bencode::encode(&vec![1, 2, 3]).unwrap();
+/// assert_eq!(encoded, b"li1ei2ei3ee");
+/// ```
+pub fn encode<T: ser::Serialize>(value: &T) -> Result<Vec<u8>> {
+    to_vec(value)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -466,6 +718,110 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_serialize_bool_as_int() {
+        let mut w = Vec::new();
+        {
+            let mut ser = Serializer::new(&mut w).bool_as_int(true);
+            ser::Serialize::serialize(&(true, false), &mut ser).unwrap();
+        }
+        assert_eq!(String::from_utf8(w).unwrap(), "li1ei0ee");
+
+        let mut map = BTreeMap::new();
+        map.insert("private", true);
+        let mut w = Vec::new();
+        {
+            let mut ser = Serializer::new(&mut w).bool_as_int(true);
+            ser::Serialize::serialize(&map, &mut ser).unwrap();
+        }
+        assert_eq!(String::from_utf8(w).unwrap(), "d7:privatei1ee");
+        assert!(to_vec(&map).is_err());
+    }
+
+    /// A struct with collection fields, for testing `omit_empty`.
+    struct Announce {
+        name: &'static str,
+        nodes: Vec<i64>,
+        extra: BTreeMap<String, i64>,
+    }
+
+    impl ser::Serialize for Announce {
+        fn serialize<S>(&self, serializer: &mut S) -> ::std::result::Result<(), S::Error>
+            where S: ser::Serializer
+        {
+            let mut state = try!(serializer.serialize_struct("Announce", 3));
+            try!(serializer.serialize_struct_elt(&mut state, "name", self.name));
+            try!(serializer.serialize_struct_elt(&mut state, "nodes", &self.nodes));
+            try!(serializer.serialize_struct_elt(&mut state, "extra", &self.extra));
+            serializer.serialize_struct_end(state)
+        }
+    }
+
+    fn to_string_omit_empty<T: ser::Serialize>(value: &T) -> String {
+        let mut w = Vec::new();
+        {
+            let mut ser = Serializer::new(&mut w).omit_empty(true);
+            value.serialize(&mut ser).unwrap();
+        }
+        String::from_utf8(w).unwrap()
+    }
+
+    #[test]
+    fn test_serialize_struct_omit_empty() {
+        let empty = Announce {
+            name: "a",
+            nodes: vec![],
+            extra: BTreeMap::new(),
+        };
+        assert_eq!(to_string(&empty).unwrap(), "d5:extrade4:name1:a5:nodeslee");
+        assert_eq!(to_string_omit_empty(&empty), "d4:name1:ae");
+
+        let mut extra = BTreeMap::new();
+        extra.insert("x".to_string(), 1);
+        let full = Announce {
+            name: "a",
+            nodes: vec![1],
+            extra: extra,
+        };
+        assert_eq!(to_string_omit_empty(&full),
+                   "d5:extrad1:xi1ee4:name1:a5:nodesli1eee");
+
+        // Only struct fields are left out, not map entries or the top-level value
+        let mut map = BTreeMap::new();
+        map.insert("nodes", Vec::<i64>::new());
+        assert_eq!(to_string_omit_empty(&map), "d5:nodeslee");
+        assert_eq!(to_string_omit_empty(&Vec::<i64>::new()), "le");
+    }
+
+    #[cfg(feature = "erased")]
+    #[test]
+    fn test_to_vec_list_erased() {
+        struct Peer {
+            ip: &'static str,
+            port: i64,
+        }
+
+        impl ser::Serialize for Peer {
+            fn serialize<S>(&self, serializer: &mut S) -> ::std::result::Result<(), S::Error>
+                where S: ser::Serializer
+            {
+                let mut state = try!(serializer.serialize_struct("Peer", 2));
+                try!(serializer.serialize_struct_elt(&mut state, "ip", self.ip));
+                try!(serializer.serialize_struct_elt(&mut state, "port", self.port));
+                serializer.serialize_struct_end(state)
+            }
+        }
+
+        let peer = Peer {
+            ip: "127.0.0.1",
+            port: 6881,
+        };
+        let items: [&dyn erased_serde::Serialize; 3] = [&42, &"spam", &peer];
+        assert_eq!(to_vec_list_erased(&items).unwrap(),
+                   &b"li42e4:spamd2:ip9:127.0.0.14:porti6881eee"[..]);
+        assert_eq!(to_vec_list_erased(&[]).unwrap(), b"le");
+    }
+
     #[test]
     fn test_serialize_isize() {
         let x: isize = 10;
@@ -536,10 +892,19 @@ mod tests {
         assert_eq!(to_string(&x).unwrap(), "i16e");
 
         let x: u64 = (i64::max_value() as u64) + 1;
-        assert!(match to_string(&x) {
-            Err(_) => true,
-            _ => false,
-        });
+        assert_eq!(to_string(&x).unwrap(), "i9223372036854775808e");
+
+        assert_eq!(to_string(&u64::max_value()).unwrap(),
+                   "i18446744073709551615e");
+    }
+
+    fn to_string_truncating_floats<T: ser::Serialize>(value: &T) -> Result<String> {
+        let mut w = Vec::new();
+        {
+            let mut ser = Serializer::new(&mut w).truncate_floats(true);
+            try!(value.serialize(&mut ser));
+        }
+        Ok(String::from_utf8(w).unwrap())
     }
 
     #[test]
@@ -547,29 +912,30 @@ mod tests {
         use std::f32::consts::PI;
         use std::f32::{INFINITY, NEG_INFINITY, NAN};
 
+        for &x in &[PI, -0.9, 0.0, INFINITY, NAN] {
+            match to_string(&x) {
+                Err(Error::Ser(ErrorCode::UnsupportedType(Type::F32))) => {}
+                other => panic!("Expected an unsupported type error, got {:?}", other),
+            }
+        }
+
         let x = PI;
-        assert_eq!(to_string(&x).unwrap(), "i3e");
+        assert_eq!(to_string_truncating_floats(&x).unwrap(), "i3e");
 
         let x: f32 = -x;
-        assert_eq!(to_string(&x).unwrap(), "i-3e");
-
-        let x = INFINITY;
-        assert!(match to_string(&x) {
-            Err(_) => true,
-            _ => false,
-        });
-
-        let x = NEG_INFINITY;
-        assert!(match to_string(&x) {
-            Err(_) => true,
-            _ => false,
-        });
-
-        let x = NAN;
-        assert!(match to_string(&x) {
-            Err(_) => true,
-            _ => false,
-        });
+        assert_eq!(to_string_truncating_floats(&x).unwrap(), "i-3e");
+        assert_eq!(to_string_truncating_floats(&-0.9f32).unwrap(), "i0e");
+
+        for &x in &[INFINITY, NEG_INFINITY, NAN] {
+            match to_string_truncating_floats(&x) {
+                Err(Error::Ser(ErrorCode::NonFiniteNumber(n))) => {
+                    assert!(n == x as f64 || n.is_nan() && x.is_nan())
+                }
+                other => panic!("Expected a non-finite number error, got {:?}", other),
+            }
+        }
+        assert_eq!(to_string_truncating_floats(&NAN).unwrap_err().to_string(),
+                   "Non-finite number encountered: NaN");
     }
 
     #[test]
@@ -577,29 +943,30 @@ mod tests {
         use std::f64::consts::PI;
         use std::f64::{INFINITY, NEG_INFINITY, NAN};
 
+        for &x in &[PI, -0.9, 0.0, INFINITY, NAN] {
+            match to_string(&x) {
+                Err(Error::Ser(ErrorCode::UnsupportedType(Type::F64))) => {}
+                other => panic!("Expected an unsupported type error, got {:?}", other),
+            }
+        }
+
         let x = PI;
-        assert_eq!(to_string(&x).unwrap(), "i3e");
+        assert_eq!(to_string_truncating_floats(&x).unwrap(), "i3e");
 
         let x: f64 = -x;
-        assert_eq!(to_string(&x).unwrap(), "i-3e");
-
-        let x = INFINITY;
-        assert!(match to_string(&x) {
-            Err(_) => true,
-            _ => false,
-        });
-
-        let x = NEG_INFINITY;
-        assert!(match to_string(&x) {
-            Err(_) => true,
-            _ => false,
-        });
-
-        let x = NAN;
-        assert!(match to_string(&x) {
-            Err(_) => true,
-            _ => false,
-        });
+        assert_eq!(to_string_truncating_floats(&x).unwrap(), "i-3e");
+        assert_eq!(to_string_truncating_floats(&-0.9f64).unwrap(), "i0e");
+
+        for &x in &[INFINITY, NEG_INFINITY, NAN] {
+            match to_string_truncating_floats(&x) {
+                Err(Error::Ser(ErrorCode::NonFiniteNumber(n))) => {
+                    assert!(n == x as f64 || n.is_nan() && x.is_nan())
+                }
+                other => panic!("Expected a non-finite number error, got {:?}", other),
+            }
+        }
+        assert_eq!(to_string_truncating_floats(&NAN).unwrap_err().to_string(),
+                   "Non-finite number encountered: NaN");
     }
 
     #[test]
@@ -619,14 +986,79 @@ mod tests {
 
     #[test]
     fn test_serialize_bytes() {
+        use serde::bytes::Bytes;
+
+        // Without the `Bytes` wrapper a `Vec<u8>` is just a sequence
         let x: Vec<u8> = vec![1, 2, 3];
         assert_eq!(to_string(&&x).unwrap(), "li1ei2ei3ee");
+
+        assert_eq!(to_vec(&Bytes::new(&[1, 2, 3])).unwrap(), b"3:\x01\x02\x03");
+        assert_eq!(to_vec(&Bytes::new(&[])).unwrap(), b"0:");
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        use de::from_slice;
+        use serde::bytes::ByteBuf;
+
+        let buf = ByteBuf::from(vec![0xff, 0x00, b':', b'e']);
+        let encoded = to_vec(&buf).unwrap();
+        assert_eq!(encoded, b"4:\xff\x00:e");
+        assert_eq!(from_slice::<ByteBuf>(&encoded).unwrap(), buf);
+    }
+
+    #[test]
+    fn test_serialize_map_with_binary_values() {
+        use serde::bytes::ByteBuf;
+
+        let mut map = BTreeMap::new();
+        map.insert("pieces".to_string(), ByteBuf::from(vec![0xff, 0xfe, 0x00]));
+        map.insert("id".to_string(), ByteBuf::from(b"\xffpeer".to_vec()));
+        assert_eq!(to_vec(&map).unwrap(), b"d2:id5:\xffpeer6:pieces3:\xff\xfe\x00e");
+    }
+
+    #[test]
+    fn test_serialize_map_with_optional_bytes() {
+        use serde::bytes::ByteBuf;
+
+        let mut map = BTreeMap::new();
+        map.insert("a", Some(ByteBuf::from(vec![0x00, 0x7f])));
+        map.insert("b", None);
+        assert_eq!(to_vec(&map).unwrap(), b"d1:a2:\x00\x7fe");
+    }
+
+    #[test]
+    fn test_serialize_cow() {
+        use std::borrow::Cow;
+
+        use serde::bytes::{ByteBuf, Bytes};
+
+        use de::from_slice;
+
+        for s in &[Cow::Borrowed("spam"), Cow::Owned("spam".to_string())] {
+            assert_eq!(to_string(s).unwrap(), "4:spam");
+            assert_eq!(from_slice::<Cow<str>>(b"4:spam").unwrap(), *s);
+        }
+
+        // serde serializes `[u8]` as a sequence, so `Cow<[u8]>` needs the `Bytes` wrapper to be
+        // written as a byte string
+        let borrowed: Cow<[u8]> = Cow::Borrowed(b"\xff\x00");
+        assert_eq!(to_vec(&borrowed).unwrap(), b"li255ei0ee");
+        for b in &[borrowed, Cow::Owned(vec![0xff, 0x00])] {
+            let encoded = to_vec(&Bytes::new(b)).unwrap();
+            assert_eq!(encoded, b"2:\xff\x00");
+            let decoded: Cow<[u8]> = Cow::Owned(from_slice::<ByteBuf>(&encoded).unwrap().into());
+            assert_eq!(decoded, *b);
+        }
     }
 
     #[test]
     fn test_serialize_unit() {
         let x = ();
-        assert_eq!(to_string(&x).unwrap(), "");
+        assert_eq!(to_string(&x).unwrap(), "de");
+
+        let x = vec![(), ()];
+        assert_eq!(to_string(&x).unwrap(), "ldedee");
     }
 
     #[test]
@@ -640,6 +1072,29 @@ mod tests {
         assert_eq!(String::from_utf8(w).unwrap(), "de");
     }
 
+    #[test]
+    fn test_serialize_empty_struct() {
+        struct Empty {}
+
+        impl ser::Serialize for Empty {
+            fn serialize<S>(&self, serializer: &mut S) -> ::std::result::Result<(), S::Error>
+                where S: ser::Serializer
+            {
+                let state = try!(serializer.serialize_struct("Empty", 0));
+                serializer.serialize_struct_end(state)
+            }
+        }
+
+        assert_eq!(to_string(&Empty {}).unwrap(), "de");
+        assert_eq!(to_string(&vec![Empty {}, Empty {}]).unwrap(), "ldedee");
+        let mut w = Vec::new();
+        {
+            let mut ser = Serializer::new(&mut w).trust_key_order(true).omit_empty(true);
+            ser::Serialize::serialize(&Empty {}, &mut ser).unwrap();
+        }
+        assert_eq!(w, b"de");
+    }
+
     #[test]
     fn test_serialize_unit_variant() {
         use serde::Serializer;
@@ -651,6 +1106,33 @@ mod tests {
         assert_eq!(String::from_utf8(w).unwrap(), "7:Variant");
     }
 
+    #[test]
+    fn test_serialize_map_keyed_by_unit_variants() {
+        use std::collections::HashMap;
+
+        #[derive(PartialEq, Eq, Hash)]
+        enum Color {
+            Red,
+            Green,
+        }
+
+        impl ser::Serialize for Color {
+            fn serialize<S>(&self, serializer: &mut S) -> ::std::result::Result<(), S::Error>
+                where S: ser::Serializer
+            {
+                match *self {
+                    Color::Red => serializer.serialize_unit_variant("Color", 0, "Red"),
+                    Color::Green => serializer.serialize_unit_variant("Color", 1, "Green"),
+                }
+            }
+        }
+
+        let mut map = HashMap::new();
+        map.insert(Color::Red, 1);
+        map.insert(Color::Green, 2);
+        assert_eq!(to_string(&map).unwrap(), "d5:Greeni2e3:Redi1ee");
+    }
+
     #[test]
     fn test_serialize_newtype_struct() {
         use serde::Serializer;
@@ -765,6 +1247,112 @@ mod tests {
         assert_eq!(to_string(&x).unwrap(), "li1e5:Helloe");
     }
 
+    #[test]
+    fn test_tuple_round_trip() {
+        use de::from_slice;
+
+        fn round_trip<T>(value: T, expected: &str)
+            where T: ser::Serialize + ::serde::Deserialize + PartialEq + ::std::fmt::Debug
+        {
+            assert_eq!(to_string(&value).unwrap(), expected);
+            assert_eq!(from_slice::<T>(expected.as_bytes()).unwrap(), value);
+        }
+
+        round_trip((1,), "li1ee");
+        round_trip((1, "a".to_string()), "li1e1:ae");
+        round_trip((1, 2, 3, 4, 5, 6, 7, 8), "li1ei2ei3ei4ei5ei6ei7ei8ee");
+        round_trip((1u8, -2i16, 3u32, -4i64, 'x', "six".to_string(), vec![7], (8, 9), 10, 11, 12,
+                    "twelve".to_string()),
+                   "li1ei-2ei3ei-4e1:x3:sixli7eeli8ei9eei10ei11ei12e6:twelvee");
+
+        // The list must hold exactly as many elements as the tuple
+        assert!(from_slice::<(i32, i32, i32)>(b"li1ei2ee").is_err());
+        assert!(from_slice::<(i32, i32, i32)>(b"li1ei2ei3ei4ee").is_err());
+    }
+
+    #[test]
+    fn test_serialize_map_sorts_by_raw_key() {
+        use std::collections::HashMap;
+
+        let mut map = HashMap::new();
+        map.insert("t", 1);
+        map.insert("id", 2);
+        map.insert("target", 3);
+        map.insert("a", 4);
+        assert_eq!(to_string(&map).unwrap(), "d1:ai4e2:idi2e1:ti1e6:targeti3ee");
+    }
+
+    #[test]
+    fn test_serialize_map_canonical_key_order() {
+        use std::collections::HashMap;
+
+        let mut map = HashMap::new();
+        map.insert("b", 3);
+        map.insert("aa", 2);
+        map.insert("a", 1);
+        assert_eq!(to_string(&map).unwrap(), "d1:ai1e2:aai2e1:bi3ee");
+
+        // Multibyte keys sort by their UTF-8 bytes, not by their encoded length prefixes
+        let mut map = HashMap::new();
+        map.insert("\u{e9}", 1);
+        map.insert("z", 2);
+        map.insert("zz", 3);
+        assert_eq!(to_string(&map).unwrap(), "d1:zi2e2:zzi3e2:\u{e9}i1ee");
+    }
+
+    #[test]
+    fn test_serialize_map_trusting_key_order() {
+        use serde::Serializer;
+
+        let mut w = Vec::new();
+        {
+            let mut ser = super::Serializer::new(&mut w).trust_key_order(true);
+            let mut state = ser.serialize_map(None).expect("Failed to serialize map");
+            ser.serialize_map_key(&mut state, "a").unwrap();
+            ser.serialize_map_value(&mut state, 1).unwrap();
+            // Entries go straight to the writer instead of being collected
+            assert!(state.data.is_empty());
+            assert_eq!(ser.writer, b"d1:ai1e");
+
+            ser.serialize_map_key(&mut state, "b").unwrap();
+            ser.serialize_map_value(&mut state, vec![2]).unwrap();
+            ser.serialize_map_end(state).unwrap();
+        }
+        assert_eq!(String::from_utf8(w).unwrap(), "d1:ai1e1:bli2eee");
+    }
+
+    #[test]
+    fn test_serialize_map_trusting_key_order_leaves_out_none() {
+        let mut map = BTreeMap::new();
+        map.insert("a", None);
+        map.insert("b", Some(1));
+        map.insert("c", None);
+
+        let mut w = Vec::new();
+        {
+            let mut ser = super::Serializer::new(&mut w).trust_key_order(true);
+            ser::Serialize::serialize(&map, &mut ser).unwrap();
+        }
+        assert_eq!(String::from_utf8(w).unwrap(), "d1:bi1ee");
+        assert_eq!(to_string(&map).unwrap(), "d1:bi1ee");
+    }
+
+    #[test]
+    fn test_serialize_nested_map_trusting_key_order() {
+        let mut inner = BTreeMap::new();
+        inner.insert("x", 1);
+        let mut outer = BTreeMap::new();
+        outer.insert("b", inner.clone());
+        outer.insert("a", inner);
+
+        let mut w = Vec::new();
+        {
+            let mut ser = super::Serializer::new(&mut w).trust_key_order(true);
+            ser::Serialize::serialize(&outer, &mut ser).unwrap();
+        }
+        assert_eq!(String::from_utf8(w).unwrap(), "d1:ad1:xi1ee1:bd1:xi1eee");
+    }
+
     #[test]
     fn test_serialize_tuple_struct() {
         use serde::Serializer;
@@ -813,4 +1401,160 @@ mod tests {
         assert!(state == State::First);
         assert_eq!(String::from_utf8(w).unwrap(), "d7:Variantl");
     }
+
+    #[test]
+    fn test_encoded_len() {
+        let mut map = BTreeMap::new();
+        map.insert("spam".to_string(), vec![1, -20, 300]);
+        map.insert("eggs".to_string(), vec![]);
+        assert_eq!(encoded_len(&map).unwrap(), to_vec(&map).unwrap().len());
+        assert_eq!(encoded_len(&"").unwrap(), 2);
+        assert!(encoded_len(&true).is_err());
+    }
+
+    #[test]
+    fn test_to_vec_with_capacity() {
+        let value = vec!["spam".to_string(); 100];
+        let len = encoded_len(&value).unwrap();
+        let encoded = to_vec_with_capacity(&value, len).unwrap();
+        assert_eq!(encoded, to_vec(&value).unwrap());
+        assert_eq!(encoded.len(), len);
+        assert!(encoded.capacity() >= len);
+
+        // Writing the exact length into a buffer of that capacity never reallocates it
+        let mut writer = Vec::with_capacity(len);
+        let (ptr, capacity) = (writer.as_ptr(), writer.capacity());
+        to_writer(&mut writer, &value).unwrap();
+        assert_eq!(writer.len(), len);
+        assert_eq!((writer.as_ptr(), writer.capacity()), (ptr, capacity));
+
+        let encoded = to_vec_with_capacity(&value, 0).unwrap();
+        assert_eq!(encoded.len(), len);
+    }
+
+    #[test]
+    fn test_serialize_nested_none() {
+        match to_vec(&Some(None::<i32>)) {
+            Err(Error::Ser(ErrorCode::NestedOption)) => {}
+            other => panic!("Expected a nested option error, got {:?}", other),
+        }
+        assert!(to_vec(&Some(Some(None::<i32>))).is_err());
+
+        let mut map = BTreeMap::new();
+        map.insert("a", Some(None::<i32>));
+        assert!(to_vec(&map).is_err());
+
+        // Only a `None` directly inside a `Some` is ambiguous; one further down is left out
+        // as usual
+        let mut map = BTreeMap::new();
+        map.insert("a", None::<i32>);
+        map.insert("b", Some(1));
+        assert_eq!(to_vec(&Some(map)).unwrap(), b"d1:bi1ee");
+        assert_eq!(to_vec(&Some(Some(1))).unwrap(), b"i1e");
+        assert_eq!(to_vec(&None::<i32>).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_serialize_sets() {
+        use std::collections::{BTreeSet, HashSet};
+
+        use de::from_slice;
+
+        let set: BTreeSet<i64> = vec![3, -1, 2].into_iter().collect();
+        assert_eq!(to_string(&set).unwrap(), "li-1ei2ei3ee");
+        assert_eq!(from_slice::<BTreeSet<i64>>(b"li3ei-1ei2ee").unwrap(), set);
+
+        let set: HashSet<i64> = vec![3, -1, 2].into_iter().collect();
+        let encoded = to_vec(&set).unwrap();
+        assert_eq!(encoded.len(), "li-1ei2ei3ee".len());
+        assert_eq!(from_slice::<HashSet<i64>>(&encoded).unwrap(), set);
+    }
+
+    /// Keeps each write as a separate chunk, to see when output reaches the writer.
+    struct RecordingWriter {
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl io::Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_serialize_map_trusting_key_order_writes_incrementally() {
+        let mut map = BTreeMap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+
+        let mut w = RecordingWriter { writes: vec![] };
+        ser::Serialize::serialize(&map, &mut Serializer::new(&mut w).trust_key_order(true))
+            .unwrap();
+        // Each key and value arrives on its own rather than in a single buffered write
+        assert_eq!(w.writes.concat(), b"d1:ai1e1:bi2ee");
+        assert_eq!(w.writes.first().unwrap(), b"d");
+        assert!(w.writes.len() >= 6);
+
+        let mut w = RecordingWriter { writes: vec![] };
+        ser::Serialize::serialize(&map, &mut Serializer::new(&mut w)).unwrap();
+        assert_eq!(w.writes.concat(), b"d1:ai1e1:bi2ee");
+        assert_eq!(w.writes.first().unwrap(), b"d");
+    }
+
+    #[test]
+    fn test_serialize_map_trusting_key_order_rejects_unsorted_keys() {
+        use serde::Serializer;
+
+        for keys in &[["b", "a"], ["a", "a"]] {
+            let mut w = Vec::new();
+            let mut ser = super::Serializer::new(&mut w).trust_key_order(true);
+            let mut state = ser.serialize_map(None).unwrap();
+            ser.serialize_map_key(&mut state, keys[0]).unwrap();
+            ser.serialize_map_value(&mut state, 1).unwrap();
+            match ser.serialize_map_key(&mut state, keys[1]) {
+                Err(Error::Ser(ErrorCode::UnsortedKeys)) => {}
+                other => panic!("Expected an unsorted keys error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_serialize_bytes_hits_output_limit() {
+        use serde::bytes::Bytes;
+        use io::LimitWriter;
+
+        let buf = vec![0xab; 1024 * 1024];
+        let mut w = LimitWriter::new(Vec::new(), 100 * 1024);
+        match ser::Serialize::serialize(&Bytes::new(&buf), &mut Serializer::new(&mut w)) {
+            Err(Error::Io(_)) => {}
+            other => panic!("Expected an io error, got {:?}", other),
+        }
+        assert_eq!(w.count(), 100 * 1024);
+        assert_eq!(w.into_inner().len(), 100 * 1024);
+    }
+
+    #[test]
+    fn test_serialize_hash_map_is_deterministic() {
+        use std::collections::HashMap;
+
+        // Each map gets its own random hasher keys, so they iterate in different orders
+        let maps: Vec<HashMap<String, Vec<i64>>> = (0..8)
+            .map(|_| (0..500).map(|i| (format!("key{}", i), vec![i, -i])).collect())
+            .collect();
+        let orders: Vec<Vec<&String>> = maps.iter().map(|map| map.keys().collect()).collect();
+        assert!(orders.iter().any(|order| *order != orders[0]));
+
+        let expected = to_vec(&maps[0]).unwrap();
+        for map in &maps {
+            assert_eq!(to_vec(map).unwrap(), expected);
+            assert_eq!(to_vec(map).unwrap(), expected);
+        }
+        let sorted: BTreeMap<&String, &Vec<i64>> = maps[0].iter().collect();
+        assert_eq!(to_vec(&sorted).unwrap(), expected);
+    }
 }
@@ -0,0 +1,59 @@
+//! A helper for encoding a `HashSet` as a sorted bencode list.
+//!
+//! Sets are encoded as lists. A `BTreeSet` always comes out sorted, but a `HashSet` comes out in
+//! whatever order it iterates in, which can differ from one run to the next. Sorting it first
+//! makes the output deterministic, so it can be hashed or compared:
+//!
+//! ```rust,ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Peers {
+//!     #[serde(serialize_with = "serde_bencode::sorted_set::serialize")]
+//!     ids: HashSet<i64>,
+//! }
+//! ```
+//!
+//! No deserialization helper is needed, as any list decodes into a `HashSet` regardless of order.
+
+use std::collections::HashSet;
+use std::hash::{BuildHasher, Hash};
+
+use serde::ser::{self, Serialize};
+
+/// Serializes a set as a list of its elements in ascending order.
+pub fn serialize<T, H, S>(set: &HashSet<T, H>, serializer: &mut S) -> Result<(), S::Error>
+    where T: Eq + Hash + Ord + Serialize,
+          H: BuildHasher,
+          S: ser::Serializer
+{
+    let mut elements: Vec<&T> = set.iter().collect();
+    elements.sort();
+    elements.serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use ser::Serializer;
+
+    #[test]
+    fn test_sorted() {
+        let set: HashSet<i64> = (0..100).rev().collect();
+        let mut w = Vec::new();
+        super::serialize(&set, &mut Serializer::new(&mut w)).unwrap();
+
+        let mut expected = "l".to_string();
+        for i in 0..100 {
+            expected.push_str(&format!("i{}e", i));
+        }
+        expected.push('e');
+        assert_eq!(String::from_utf8(w).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut w = Vec::new();
+        super::serialize(&HashSet::<String>::new(), &mut Serializer::new(&mut w)).unwrap();
+        assert_eq!(w, b"le");
+    }
+}
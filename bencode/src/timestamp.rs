@@ -0,0 +1,133 @@
+//! Helpers for encoding durations and points in time as a whole number of seconds, which is how
+//! bencoded formats represent them (e.g. a tracker's `interval` or a torrent's `creation date`).
+//!
+//! Use them with serde's field attributes:
+//!
+//! ```rust,ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct TrackerResponse {
+//!     #[serde(serialize_with = "serde_bencode::timestamp::serialize",
+//!             deserialize_with = "serde_bencode::timestamp::deserialize")]
+//!     interval: Duration,
+//! }
+//! ```
+//!
+//! Any sub-second precision is truncated on serialization.
+
+use std::time::Duration;
+
+use serde::de;
+use serde::ser;
+
+/// Serializes a `Duration` as a bencode integer number of seconds.
+pub fn serialize<S>(duration: &Duration, serializer: &mut S) -> Result<(), S::Error>
+    where S: ser::Serializer
+{
+    serializer.serialize_u64(duration.as_secs())
+}
+
+/// Deserializes a `Duration` from a bencode integer number of seconds. Negative values are
+/// rejected.
+pub fn deserialize<D>(deserializer: &mut D) -> Result<Duration, D::Error>
+    where D: de::Deserializer
+{
+    deserializer.deserialize_u64(SecondsVisitor)
+}
+
+struct SecondsVisitor;
+
+impl de::Visitor for SecondsVisitor {
+    type Value = Duration;
+
+    fn visit_i64<E>(&mut self, v: i64) -> Result<Duration, E>
+        where E: de::Error
+    {
+        if v < 0 {
+            return Err(E::invalid_value(&format!("negative number of seconds: {}", v)));
+        }
+        Ok(Duration::from_secs(v as u64))
+    }
+
+    fn visit_u64<E>(&mut self, v: u64) -> Result<Duration, E>
+        where E: de::Error
+    {
+        Ok(Duration::from_secs(v))
+    }
+}
+
+/// The same helpers for `SystemTime`, encoded as seconds since the UNIX epoch.
+pub mod system_time {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use serde::de;
+    use serde::ser;
+
+    /// Serializes a `SystemTime` as a bencode integer number of seconds since the UNIX epoch.
+    /// Times before the epoch cannot be represented and are rejected.
+    pub fn serialize<S>(time: &SystemTime, serializer: &mut S) -> Result<(), S::Error>
+        where S: ser::Serializer
+    {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => super::serialize(&since_epoch, serializer),
+            Err(_) => Err(ser::Error::custom("Cannot serialize a time before the UNIX epoch")),
+        }
+    }
+
+    /// Deserializes a `SystemTime` from a bencode integer number of seconds since the UNIX
+    /// epoch.
+    pub fn deserialize<D>(deserializer: &mut D) -> Result<SystemTime, D::Error>
+        where D: de::Deserializer
+    {
+        super::deserialize(deserializer).map(|since_epoch| UNIX_EPOCH + since_epoch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use de::Deserializer;
+    use read::SliceRead;
+    use ser::Serializer;
+
+    #[test]
+    fn test_serialize_duration() {
+        let mut w = Vec::new();
+        serialize(&Duration::new(1800, 500), &mut Serializer::new(&mut w))
+            .expect("Failed to serialize duration");
+        assert_eq!(String::from_utf8(w).unwrap(), "i1800e");
+    }
+
+    #[test]
+    fn test_deserialize_duration() {
+        let mut de = Deserializer::new(SliceRead::new(b"i1800e"));
+        assert_eq!(deserialize(&mut de).unwrap(), Duration::from_secs(1800));
+    }
+
+    #[test]
+    fn test_deserialize_negative_duration() {
+        let mut de = Deserializer::new(SliceRead::new(b"i-5e"));
+        assert!(deserialize(&mut de).is_err());
+    }
+
+    #[test]
+    fn test_system_time_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_secs(1476576000);
+
+        let mut w = Vec::new();
+        system_time::serialize(&time, &mut Serializer::new(&mut w))
+            .expect("Failed to serialize system time");
+        assert_eq!(String::from_utf8(w.clone()).unwrap(), "i1476576000e");
+
+        let mut de = Deserializer::new(SliceRead::new(&w));
+        assert_eq!(system_time::deserialize(&mut de).unwrap(), time);
+    }
+
+    #[test]
+    fn test_serialize_system_time_before_epoch() {
+        let time = UNIX_EPOCH - Duration::from_secs(1);
+        let mut w = Vec::new();
+        assert!(system_time::serialize(&time, &mut Serializer::new(&mut w)).is_err());
+    }
+}
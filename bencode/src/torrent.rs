@@ -0,0 +1,269 @@
+//! Types describing the metainfo (`.torrent`) file format, as laid out in
+//! [BEP 3](http://bittorrent.org/beps/bep_0003.html).
+//!
+//! Only the keys needed to locate a torrent's data are decoded; any other keys are skipped.
+
+use serde::bytes::ByteBuf;
+use serde::de;
+
+use super::de::from_slice;
+use super::error::{self, Error, ErrorCode};
+
+/// A metainfo file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Torrent {
+    /// The tracker URL, if any (`announce`)
+    pub announce: Option<String>,
+    /// The info dict (`info`)
+    pub info: Info,
+}
+
+/// The info dict of a metainfo file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Info {
+    /// The suggested name of the file, or of the directory for a multi-file torrent (`name`)
+    pub name: String,
+    /// The number of bytes in each piece (`piece length`)
+    pub piece_length: i64,
+    /// The concatenated SHA1 hashes of every piece (`pieces`)
+    pub pieces: Vec<u8>,
+    /// The length of the file, for a single-file torrent (`length`)
+    pub length: Option<i64>,
+    /// The files making up a multi-file torrent (`files`)
+    pub files: Option<Vec<TorrentFile>>,
+}
+
+/// One entry of the `files` list of a multi-file torrent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TorrentFile {
+    /// The length of the file in bytes (`length`)
+    pub length: i64,
+    /// The path of the file relative to the torrent's directory, one element per path component
+    /// (`path`). Components are kept as raw bytes since they are not required to be UTF-8.
+    pub path: Vec<Vec<u8>>,
+}
+
+impl Torrent {
+    /// Parses a metainfo file. Unlike `from_slice`, input that isn't a dict, such as some other
+    /// file passed by mistake, fails with `ErrorCode::NotATorrent` before anything is decoded.
+    pub fn from_bytes(input: &[u8]) -> error::Result<Torrent> {
+        match input.first() {
+            Some(&b'd') => from_slice(input),
+            _ => Err(Error::Syntax(ErrorCode::NotATorrent, 0)),
+        }
+    }
+}
+
+impl de::Deserialize for Torrent {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Torrent, D::Error>
+        where D: de::Deserializer
+    {
+        const FIELDS: &[&str] = &["announce", "info"];
+        deserializer.deserialize_struct("Torrent", FIELDS, TorrentVisitor)
+    }
+}
+
+struct TorrentVisitor;
+
+impl de::Visitor for TorrentVisitor {
+    type Value = Torrent;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<Torrent, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut announce = None;
+        let mut info = None;
+        while let Some(key) = try!(visitor.visit_key::<String>()) {
+            match &key[..] {
+                "announce" => announce = Some(try!(visitor.visit_value())),
+                "info" => info = Some(try!(visitor.visit_value())),
+                _ => {
+                    try!(visitor.visit_value::<de::impls::IgnoredAny>());
+                }
+            }
+        }
+        try!(visitor.end());
+
+        let info = match info {
+            Some(info) => info,
+            None => try!(visitor.missing_field("info")),
+        };
+        Ok(Torrent {
+            announce: announce,
+            info: info,
+        })
+    }
+}
+
+impl de::Deserialize for Info {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Info, D::Error>
+        where D: de::Deserializer
+    {
+        const FIELDS: &[&str] = &["name", "piece length", "pieces", "length", "files"];
+        deserializer.deserialize_struct("Info", FIELDS, InfoVisitor)
+    }
+}
+
+struct InfoVisitor;
+
+impl de::Visitor for InfoVisitor {
+    type Value = Info;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<Info, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut name = None;
+        let mut piece_length = None;
+        let mut pieces = None;
+        let mut length = None;
+        let mut files = None;
+        while let Some(key) = try!(visitor.visit_key::<String>()) {
+            match &key[..] {
+                "name" => name = Some(try!(visitor.visit_value())),
+                "piece length" => piece_length = Some(try!(visitor.visit_value())),
+                "pieces" => pieces = Some(try!(visitor.visit_value::<ByteBuf>()).into()),
+                "length" => length = Some(try!(visitor.visit_value())),
+                "files" => files = Some(try!(visitor.visit_value())),
+                _ => {
+                    try!(visitor.visit_value::<de::impls::IgnoredAny>());
+                }
+            }
+        }
+        try!(visitor.end());
+
+        let name = match name {
+            Some(name) => name,
+            None => try!(visitor.missing_field("name")),
+        };
+        let piece_length = match piece_length {
+            Some(piece_length) => piece_length,
+            None => try!(visitor.missing_field("piece length")),
+        };
+        let pieces = match pieces {
+            Some(pieces) => pieces,
+            None => try!(visitor.missing_field("pieces")),
+        };
+        Ok(Info {
+            name: name,
+            piece_length: piece_length,
+            pieces: pieces,
+            length: length,
+            files: files,
+        })
+    }
+}
+
+impl de::Deserialize for TorrentFile {
+    fn deserialize<D>(deserializer: &mut D) -> Result<TorrentFile, D::Error>
+        where D: de::Deserializer
+    {
+        const FIELDS: &[&str] = &["length", "path"];
+        deserializer.deserialize_struct("TorrentFile", FIELDS, TorrentFileVisitor)
+    }
+}
+
+struct TorrentFileVisitor;
+
+impl de::Visitor for TorrentFileVisitor {
+    type Value = TorrentFile;
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<TorrentFile, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut length = None;
+        let mut path = None;
+        while let Some(key) = try!(visitor.visit_key::<String>()) {
+            match &key[..] {
+                "length" => length = Some(try!(visitor.visit_value())),
+                "path" => {
+                    let components: Vec<ByteBuf> = try!(visitor.visit_value());
+                    path = Some(components.into_iter().map(Into::into).collect());
+                }
+                _ => {
+                    try!(visitor.visit_value::<de::impls::IgnoredAny>());
+                }
+            }
+        }
+        try!(visitor.end());
+
+        let length = match length {
+            Some(length) => length,
+            None => try!(visitor.missing_field("length")),
+        };
+        let path = match path {
+            Some(path) => path,
+            None => try!(visitor.missing_field("path")),
+        };
+        Ok(TorrentFile {
+            length: length,
+            path: path,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MULTI_FILE: &[u8] = b"d8:announce31:http://tracker.example/announce4:infod5:filesl\
+                               d6:lengthi1024e4:pathl3:dir9:first.txteed6:lengthi42e4:pathl\
+                               10:second.txteee4:name7:example12:piece lengthi16384e6:pieces\
+                               20:aaaaaaaaaaaaaaaaaaaaee";
+
+    #[test]
+    fn test_multi_file_torrent() {
+        let torrent: Torrent = from_slice(MULTI_FILE).unwrap();
+        assert_eq!(torrent.announce,
+                   Some("http://tracker.example/announce".to_string()));
+        assert_eq!(torrent.info.name, "example");
+        assert_eq!(torrent.info.piece_length, 16384);
+        assert_eq!(torrent.info.pieces, vec![b'a'; 20]);
+        assert_eq!(torrent.info.length, None);
+        assert_eq!(torrent.info.files,
+                   Some(vec![TorrentFile {
+                                 length: 1024,
+                                 path: vec![b"dir".to_vec(), b"first.txt".to_vec()],
+                             },
+                             TorrentFile {
+                                 length: 42,
+                                 path: vec![b"second.txt".to_vec()],
+                             }]));
+    }
+
+    #[test]
+    fn test_single_file_torrent() {
+        let input = b"d4:infod6:lengthi7e4:name8:file.txt12:piece lengthi16384e6:pieces0:ee";
+        let torrent: Torrent = from_slice(input).unwrap();
+        assert_eq!(torrent.announce, None);
+        assert_eq!(torrent.info.length, Some(7));
+        assert_eq!(torrent.info.files, None);
+    }
+
+    #[test]
+    fn test_torrent_file_missing_path() {
+        assert!(from_slice::<TorrentFile>(b"d6:lengthi1ee").is_err());
+    }
+
+    #[test]
+    fn test_torrent_file_ignores_unknown_keys() {
+        let file: TorrentFile = from_slice(b"d6:lengthi1e6:md5sum0:4:pathl1:aee").unwrap();
+        assert_eq!(file.path, vec![b"a".to_vec()]);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        assert_eq!(Torrent::from_bytes(MULTI_FILE).unwrap(),
+                   from_slice::<Torrent>(MULTI_FILE).unwrap());
+        for input in &[&b"i42e"[..], b"l4:spame", b"4:spam", b""] {
+            match Torrent::from_bytes(input) {
+                Err(Error::Syntax(ErrorCode::NotATorrent, 0)) => {}
+                other => panic!("Expected a not-a-torrent error, got {:?}", other),
+            }
+        }
+        // A dict that isn't a torrent is reported as such by the decoder
+        match Torrent::from_bytes(b"d4:spami1ee") {
+            Err(Error::Value(de::value::Error::MissingField("info"))) => {}
+            other => panic!("Expected a missing field error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,1668 @@
+//! A loosely-typed representation of bencode data, for when the shape of a document isn't known
+//! ahead of time.
+
+use std::collections::{btree_map, BTreeMap};
+use std::fmt::{self, Write};
+use std::iter::FromIterator;
+use std::result;
+use std::slice;
+use std::str;
+
+use serde::bytes::{ByteBuf, Bytes};
+use serde::{de, ser};
+use serde::de::Deserialize;
+use serde::de::impls::IgnoredAny;
+
+use super::de::{from_slice, Deserializer};
+use super::ser::to_writer;
+use super::error::{Error, ErrorCode, Result};
+use super::event::{Event, EventReader};
+use super::io::HashWriter;
+use super::read::{Read, SliceRead};
+
+/// Implements a `Value` accessor returning the integer held by an `Int` as the given type, or
+/// `None` if this is not an `Int` or its value does not fit.
+macro_rules! int_accessor {
+    ($method:ident, $ty:ident) => {
+        #[doc = concat!("Returns the value of an `Int` as a `", stringify!($ty), "`, or `None` if \
+                         this is not an `Int` or its value is out of range for `",
+                        stringify!($ty), "`.")]
+        pub fn $method(&self) -> Option<$ty> {
+            match *self {
+                Value::Int(n) if n as i128 >= $ty::min_value() as i128 &&
+                                 n as i128 <= $ty::max_value() as i128 => Some(n as $ty),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Represents any valid bencode value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// An integer, e.g. `i42e`
+    Int(i64),
+    /// A byte string, e.g. `4:spam`. The contents are not required to be valid UTF-8.
+    ByteString(Vec<u8>),
+    /// A list of values, e.g. `l4:spami42ee`
+    List(Vec<Value>),
+    /// A dictionary of byte string keys to values, e.g. `d3:cow3:mooe`. Entries are kept sorted
+    /// by the raw bytes of their keys, as bencode requires, so a `Value` always re-encodes
+    /// canonically whatever order its keys were read or inserted in.
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    /// Computes the number of bytes this value occupies when bencoded, without actually encoding
+    /// it.
+    pub fn serialized_len(&self) -> usize {
+        match *self {
+            // i<digits>e
+            Value::Int(n) => {
+                let sign = if n < 0 { 1 } else { 0 };
+                2 + sign + digit_count(n.wrapping_abs() as u64)
+            }
+            // <len>:<bytes>
+            Value::ByteString(ref bytes) => string_len(bytes),
+            // l<values>e
+            Value::List(ref list) => 2 + list.iter().map(Value::serialized_len).sum::<usize>(),
+            // d<key><value>e
+            Value::Dict(ref dict) => {
+                2 +
+                dict.iter()
+                    .map(|(k, v)| string_len(k) + v.serialized_len())
+                    .sum::<usize>()
+            }
+        }
+    }
+
+    /// Hashes the canonical encoding of this value, for deduplicating or caching documents. Dict
+    /// entries are always encoded in key order, so values that are `semantic_eq` hash equal
+    /// however their dicts were built. The hash is 64-bit FNV-1a, which gives the same result on
+    /// every run and platform, so it can be stored; it is not a cryptographic hash.
+    pub fn content_hash(&self) -> u64 {
+        let mut w = HashWriter::new();
+        // Every `Value` can be encoded, and writing to a `HashWriter` cannot fail
+        let _ = to_writer(&mut w, self);
+        w.finish()
+    }
+
+    /// Removes every entry of a `Dict` for which `f` returns `false`, keeping the rest in order.
+    /// Does nothing if this value is not a `Dict`.
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&[u8], &Value) -> bool
+    {
+        if let Value::Dict(ref mut dict) = *self {
+            dict.retain(|key, value| f(key, value));
+        }
+    }
+
+    /// Returns the number of elements of a `List`, entries of a `Dict`, or bytes of a
+    /// `ByteString`. Returns `None` for an `Int`, which has no length.
+    pub fn len(&self) -> Option<usize> {
+        match *self {
+            Value::Int(_) => None,
+            Value::ByteString(ref bytes) => Some(bytes.len()),
+            Value::List(ref list) => Some(list.len()),
+            Value::Dict(ref dict) => Some(dict.len()),
+        }
+    }
+
+    /// Returns `true` if this is an empty `List`, `Dict`, or `ByteString`. An `Int` is never
+    /// empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    int_accessor!(as_i64, i64);
+    int_accessor!(as_i32, i32);
+    int_accessor!(as_i16, i16);
+    int_accessor!(as_i8, i8);
+    int_accessor!(as_u64, u64);
+    int_accessor!(as_u32, u32);
+    int_accessor!(as_u16, u16);
+    int_accessor!(as_u8, u8);
+
+    /// Returns the contents of a `ByteString`, or `None` if this is not a `ByteString`.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match *self {
+            Value::ByteString(ref bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Returns the contents of a `ByteString` as a `str`, or `None` if this is not a `ByteString`
+    /// or its contents are not valid UTF-8.
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|bytes| str::from_utf8(bytes).ok())
+    }
+
+    /// Returns the elements of a `List`, or `None` if this is not a `List`.
+    pub fn as_list(&self) -> Option<&Vec<Value>> {
+        match *self {
+            Value::List(ref list) => Some(list),
+            _ => None,
+        }
+    }
+
+    /// Returns the entries of a `Dict`, or `None` if this is not a `Dict`.
+    pub fn as_dict(&self) -> Option<&BTreeMap<Vec<u8>, Value>> {
+        match *self {
+            Value::Dict(ref dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    /// Looks up `key` in a `Dict`, e.g. `value.get("info").and_then(|info| info.get("name"))`.
+    /// Returns `None` if the key is missing or this is not a `Dict`.
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<&Value> {
+        self.as_dict().and_then(|dict| dict.get(key.as_ref()))
+    }
+
+    /// Returns the type of this value.
+    pub fn kind(&self) -> Kind {
+        match *self {
+            Value::Int(_) => Kind::Int,
+            Value::ByteString(_) => Kind::ByteString,
+            Value::List(_) => Kind::List,
+            Value::Dict(_) => Kind::Dict,
+        }
+    }
+
+    /// Compares two values treating dicts as unordered maps: each dict must hold the same keys,
+    /// with values that are themselves `semantic_eq`. Lists must match element by element.
+    ///
+    /// For today's `BTreeMap`-backed dicts this agrees with `==`, but unlike `==` it does not
+    /// depend on the order the dict keeps its entries in.
+    pub fn semantic_eq(&self, other: &Value) -> bool {
+        match *self {
+            Value::List(ref a) => {
+                match *other {
+                    Value::List(ref b) => {
+                        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.semantic_eq(b))
+                    }
+                    _ => false,
+                }
+            }
+            Value::Dict(ref a) => {
+                match *other {
+                    Value::Dict(ref b) => {
+                        a.len() == b.len() &&
+                        a.iter().all(|(key, a)| match b.get(key) {
+                            Some(b) => a.semantic_eq(b),
+                            None => false,
+                        })
+                    }
+                    _ => false,
+                }
+            }
+            Value::Int(_) | Value::ByteString(_) => self == other,
+        }
+    }
+
+    /// Checks that this is a dict holding each of `keys` with a value of the given kind, such as
+    /// after decoding a torrent without a schema. Every problem is reported at once, in an
+    /// `ErrorCode::RequiredKeys`. A value that isn't a dict is missing all of the keys.
+    pub fn require(&self, keys: &[(&str, Kind)]) -> Result<()> {
+        let mut missing = vec![];
+        let mut mistyped = vec![];
+        for &(key, kind) in keys {
+            let found = match *self {
+                Value::Dict(ref dict) => dict.get(key.as_bytes()),
+                _ => None,
+            };
+            match found {
+                None => missing.push(key.to_string()),
+                Some(value) if value.kind() != kind => {
+                    mistyped.push((key.to_string(), kind, value.kind()))
+                }
+                Some(_) => {}
+            }
+        }
+        if missing.is_empty() && mistyped.is_empty() {
+            return Ok(());
+        }
+        let code = ErrorCode::RequiredKeys {
+            missing: missing,
+            mistyped: mistyped,
+        };
+        Err(Error::Syntax(code, 0))
+    }
+
+    /// Checks that this is a dict holding exactly `n` entries, failing with
+    /// `ErrorCode::DictLength` if it holds some other number, or `ErrorCode::UnexpectedKind` if
+    /// it is not a dict at all.
+    pub fn expect_dict_len(&self, n: usize) -> Result<()> {
+        let code = match *self {
+            Value::Dict(ref dict) if dict.len() == n => return Ok(()),
+            Value::Dict(ref dict) => {
+                ErrorCode::DictLength {
+                    expected: n,
+                    found: dict.len(),
+                }
+            }
+            _ => {
+                ErrorCode::UnexpectedKind {
+                    expected: Kind::Dict,
+                    found: self.kind(),
+                }
+            }
+        };
+        Err(Error::Syntax(code, 0))
+    }
+
+    /// Follows `path` through nested dicts, one key per level, and returns an owned copy of the
+    /// value found there. The copy shares nothing with `self`, so it can be sent to another
+    /// thread while the tree stays behind, say, an `Arc`. Returns `None` if some key is missing
+    /// or a value along the way is not a dict. An empty path copies the whole value.
+    pub fn get_path(&self, path: &[&[u8]]) -> Option<Value> {
+        let mut value = self;
+        for key in path {
+            value = match *value {
+                Value::Dict(ref dict) if dict.contains_key(*key) => &dict[*key],
+                _ => return None,
+            };
+        }
+        Some(value.clone())
+    }
+
+    /// Returns an iterator over this value and every value nested within it, depth-first, with
+    /// containers yielded before their contents. Dict values are visited in key order.
+    pub fn iter_all<'a>(&'a self) -> Nodes<'a> {
+        Nodes { stack: vec![self] }
+    }
+
+    /// Returns an iterator over the elements of a `List`, or `None` if this is not a `List`.
+    pub fn iter_list<'a>(&'a self) -> Option<slice::Iter<'a, Value>> {
+        match *self {
+            Value::List(ref list) => Some(list.iter()),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over the keys and values of a `Dict` in key order, or `None` if this
+    /// is not a `Dict`.
+    pub fn iter_dict<'a>(&'a self) -> Option<DictEntries<'a>> {
+        match *self {
+            Value::Dict(ref dict) => Some(DictEntries { iter: dict.iter() }),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over the `Int` and `ByteString` values nested within this value, in
+    /// the same order as `iter_all`.
+    pub fn leaves<'a>(&'a self) -> Leaves<'a> {
+        Leaves { nodes: self.iter_all() }
+    }
+
+    /// Rebuilds this value by applying `f` to every node, children first: each list and dict is
+    /// handed to `f` only after its elements have been transformed, and the value itself last.
+    /// `f` can change a node's kind, or prune a container by replacing it.
+    pub fn transform<F>(self, mut f: F) -> Value
+        where F: FnMut(Value) -> Value
+    {
+        self.transform_with(&mut f)
+    }
+
+    fn transform_with<F>(self, f: &mut F) -> Value
+        where F: FnMut(Value) -> Value
+    {
+        let value = match self {
+            Value::List(list) => {
+                Value::List(list.into_iter().map(|value| value.transform_with(f)).collect())
+            }
+            Value::Dict(dict) => {
+                Value::Dict(dict.into_iter()
+                    .map(|(key, value)| (key, value.transform_with(f)))
+                    .collect())
+            }
+            value => value,
+        };
+        f(value)
+    }
+
+    /// Decodes a single bencode value, failing as soon as the input exceeds any of the given
+    /// limits. This bounds the memory use and stack depth of decoding untrusted input, such as
+    /// metadata received from a peer.
+    ///
+    /// * `max_depth` bounds how deeply lists and dicts nest; a top-level dict is at depth 1.
+    /// * `max_nodes` bounds the total number of values, counting dict keys.
+    /// * `max_string` bounds the declared length of any one string.
+    ///
+    /// The errors are `ErrorCode::DepthLimitExceeded`, `ErrorCode::NodeLimitExceeded` and
+    /// `ErrorCode::StringTooLong` respectively.
+    ///
+    /// **Dict keys are not checked.** As with `from_slice_lenient`, keys that are out of order
+    /// are accepted and sorted, and of a repeated key only the last occurrence is kept. The
+    /// result may therefore not re-encode to the input, so hash the input itself rather than the
+    /// decoded value, or decode with a `Deserializer` set to `check_key_order` to reject such
+    /// input.
+    pub fn from_slice_limited(input: &[u8],
+                              max_depth: usize,
+                              max_nodes: usize,
+                              max_string: usize)
+                              -> Result<Value> {
+        let de = Deserializer::new(SliceRead::new(input))
+            .max_depth(max_depth)
+            .max_nodes(max_nodes)
+            .max_string_len(max_string);
+        let mut events = EventReader::from_deserializer(de);
+        let first = try!(next_event(&mut events));
+        build(&mut events, first)
+    }
+
+    /// Renders this value as indented, human-readable text, for inspecting parsed documents.
+    ///
+    /// Byte strings that are valid UTF-8 are shown as quoted strings, and any others are rendered
+    /// as `opts.binary` specifies. Strings longer than `opts.max_string_len` bytes are cut short
+    /// and followed by their full length, which keeps fields like a torrent's `pieces` readable.
+    pub fn pretty(&self, opts: PrettyOpts) -> String {
+        let mut out = String::new();
+        write_pretty(&mut out, self, &opts, 0);
+        out
+    }
+}
+
+/// The type of a `Value`, without its contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Int,
+    ByteString,
+    List,
+    Dict,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Kind::Int => "integer",
+            Kind::ByteString => "byte string",
+            Kind::List => "list",
+            Kind::Dict => "dictionary",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The default value is an empty `Dict`, the usual top-level value of a bencode document.
+impl Default for Value {
+    fn default() -> Self {
+        Value::Dict(BTreeMap::new())
+    }
+}
+
+/// Reads any bencode value, e.g. `let v: Value = from_slice(&data)?` to inspect a document
+/// without defining types for it. Strings are kept as raw bytes whether or not they are valid
+/// UTF-8.
+impl de::Deserialize for Value {
+    fn deserialize<D>(deserializer: &mut D) -> result::Result<Value, D::Error>
+        where D: de::Deserializer
+    {
+        deserializer.deserialize(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl de::Visitor for ValueVisitor {
+    type Value = Value;
+
+    fn visit_i64<E>(&mut self, v: i64) -> result::Result<Value, E>
+        where E: de::Error
+    {
+        Ok(Value::Int(v))
+    }
+
+    fn visit_u64<E>(&mut self, v: u64) -> result::Result<Value, E>
+        where E: de::Error
+    {
+        if v > i64::max_value() as u64 {
+            return Err(de::Error::invalid_value(&format!("integer {} does not fit in an i64", v)));
+        }
+        Ok(Value::Int(v as i64))
+    }
+
+    fn visit_str<E>(&mut self, v: &str) -> result::Result<Value, E>
+        where E: de::Error
+    {
+        Ok(Value::ByteString(v.as_bytes().to_vec()))
+    }
+
+    fn visit_string<E>(&mut self, v: String) -> result::Result<Value, E>
+        where E: de::Error
+    {
+        Ok(Value::ByteString(v.into_bytes()))
+    }
+
+    fn visit_bytes<E>(&mut self, v: &[u8]) -> result::Result<Value, E>
+        where E: de::Error
+    {
+        Ok(Value::ByteString(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(&mut self, v: Vec<u8>) -> result::Result<Value, E>
+        where E: de::Error
+    {
+        Ok(Value::ByteString(v))
+    }
+
+    fn visit_seq<V>(&mut self, mut visitor: V) -> result::Result<Value, V::Error>
+        where V: de::SeqVisitor
+    {
+        let mut list = vec![];
+        while let Some(value) = try!(visitor.visit()) {
+            list.push(value);
+        }
+        try!(visitor.end());
+        Ok(Value::List(list))
+    }
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> result::Result<Value, V::Error>
+        where V: de::MapVisitor
+    {
+        let mut dict = BTreeMap::new();
+        while let Some(key) = try!(visitor.visit_key::<ByteBuf>()) {
+            let value = try!(visitor.visit_value());
+            dict.insert(key.into(), value);
+        }
+        try!(visitor.end());
+        Ok(Value::Dict(dict))
+    }
+}
+
+/// Writes the value back out as bencode, so a document can be parsed into a `Value`, changed and
+/// re-encoded.
+impl ser::Serialize for Value {
+    fn serialize<S>(&self, serializer: &mut S) -> result::Result<(), S::Error>
+        where S: ser::Serializer
+    {
+        match *self {
+            Value::Int(n) => serializer.serialize_i64(n),
+            Value::ByteString(ref bytes) => serializer.serialize_bytes(bytes),
+            Value::List(ref list) => {
+                let mut state = try!(serializer.serialize_seq(Some(list.len())));
+                for value in list {
+                    try!(serializer.serialize_seq_elt(&mut state, value));
+                }
+                serializer.serialize_seq_end(state)
+            }
+            Value::Dict(ref dict) => {
+                let mut state = try!(serializer.serialize_map(Some(dict.len())));
+                for (key, value) in dict {
+                    try!(serializer.serialize_map_key(&mut state, Bytes::new(key)));
+                    try!(serializer.serialize_map_value(&mut state, value));
+                }
+                serializer.serialize_map_end(state)
+            }
+        }
+    }
+}
+
+/// A bencode value that has not been decoded yet, held as the bytes it was read from.
+///
+/// This is useful for large documents where only some parts are ever looked at, such as a torrent
+/// whose huge `info` dict may not be needed: `entries` splits a dict into its keys and the raw
+/// bytes of each value, and `force` decodes a value only when it is wanted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LazyValue<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> LazyValue<'a> {
+    /// Wraps the encoding of a single value. Nothing is checked until the value is used.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        LazyValue { bytes: bytes }
+    }
+
+    /// The undecoded bytes of this value.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Decodes this value.
+    pub fn force(&self) -> Result<Value> {
+        from_slice(self.bytes)
+    }
+
+    /// Reads the keys of a dict, leaving each value undecoded. Values are only skipped over, far
+    /// enough to find where they end: strings are not read at all, and lists and dicts are
+    /// walked without building anything.
+    pub fn entries(&self) -> Result<BTreeMap<Vec<u8>, LazyValue<'a>>> {
+        const END: u8 = b'e';
+
+        let mut de = Deserializer::new(SliceRead::new(self.bytes));
+        try!(de.expect_container(b'd'));
+        try!(de.next_char());
+        let mut entries = BTreeMap::new();
+        loop {
+            let ch = try!(de.next_char());
+            match ch {
+                END => break,
+                b'0'...b'9' => {
+                    let key = try!(de.read_string(ch));
+                    let start = de.position();
+                    try!(IgnoredAny::deserialize(&mut de));
+                    entries.insert(key, LazyValue::new(&self.bytes[start..de.position()]));
+                }
+                _ => return Err(de.unexpected_token(ch)),
+            }
+        }
+        try!(de.end());
+        Ok(entries)
+    }
+}
+
+/// How `Value::pretty` renders byte strings that are not valid UTF-8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryRendering {
+    /// Lowercase hex digits, e.g. `<hex ff00>`
+    Hex,
+    /// Standard base64 with padding, e.g. `<base64 /wA=>`
+    Base64,
+    /// A byte string literal with non-printable bytes escaped, e.g. `b"\xff\x00"`
+    Escaped,
+}
+
+/// Options for `Value::pretty`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrettyOpts {
+    /// The number of spaces to indent each level of nesting by
+    pub indent: usize,
+    /// The number of bytes of a string to show before truncating it, or `None` to show strings
+    /// in full
+    pub max_string_len: Option<usize>,
+    /// How to render byte strings that are not valid UTF-8
+    pub binary: BinaryRendering,
+}
+
+/// Indents by two spaces, shows strings in full, and renders binary strings as hex.
+impl Default for PrettyOpts {
+    fn default() -> Self {
+        PrettyOpts {
+            indent: 2,
+            max_string_len: None,
+            binary: BinaryRendering::Hex,
+        }
+    }
+}
+
+/// A `Value` equals a string if it is a `ByteString` holding the same bytes.
+impl PartialEq<str> for Value {
+    fn eq(&self, other: &str) -> bool {
+        *self == *other.as_bytes()
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Value {
+    fn eq(&self, other: &&'a str) -> bool {
+        *self == **other
+    }
+}
+
+/// A `Value` equals a byte slice if it is a `ByteString` holding the same bytes.
+impl PartialEq<[u8]> for Value {
+    fn eq(&self, other: &[u8]) -> bool {
+        match *self {
+            Value::ByteString(ref bytes) => &bytes[..] == other,
+            _ => false,
+        }
+    }
+}
+
+/// A `Value` equals an integer if it is an `Int` holding the same number.
+impl PartialEq<i64> for Value {
+    fn eq(&self, other: &i64) -> bool {
+        match *self {
+            Value::Int(n) => n == *other,
+            _ => false,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::ByteString(s.into_bytes())
+    }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Self {
+        Value::ByteString(s.as_bytes().to_vec())
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(list: Vec<Value>) -> Self {
+        Value::List(list)
+    }
+}
+
+impl From<BTreeMap<Vec<u8>, Value>> for Value {
+    fn from(dict: BTreeMap<Vec<u8>, Value>) -> Self {
+        Value::Dict(dict)
+    }
+}
+
+/// Collects key-value pairs into a `Dict`, e.g.
+/// `vec![("port", Value::from(6881))].into_iter().collect::<Value>()`. A later entry replaces
+/// an earlier one with the same key.
+impl<K, V> FromIterator<(K, V)> for Value
+    where K: Into<Vec<u8>>,
+          V: Into<Value>
+{
+    fn from_iter<I>(iter: I) -> Self
+        where I: IntoIterator<Item = (K, V)>
+    {
+        Value::Dict(iter.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+/// An iterator over the entries of a `Value::Dict`. See `Value::iter_dict`.
+pub struct DictEntries<'a> {
+    iter: btree_map::Iter<'a, Vec<u8>, Value>,
+}
+
+impl<'a> Iterator for DictEntries<'a> {
+    type Item = (&'a [u8], &'a Value);
+
+    fn next(&mut self) -> Option<(&'a [u8], &'a Value)> {
+        self.iter.next().map(|(key, value)| (&key[..], value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// A depth-first iterator over every node of a `Value`. See `Value::iter_all`.
+pub struct Nodes<'a> {
+    stack: Vec<&'a Value>,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = &'a Value;
+
+    fn next(&mut self) -> Option<&'a Value> {
+        let stack = &mut self.stack;
+        stack.pop().inspect(|value| {
+            match **value {
+                Value::List(ref list) => stack.extend(list.iter().rev()),
+                Value::Dict(ref dict) => stack.extend(dict.values().rev()),
+                _ => {}
+            }
+        })
+    }
+}
+
+/// A depth-first iterator over the scalar values of a `Value`. See `Value::leaves`.
+pub struct Leaves<'a> {
+    nodes: Nodes<'a>,
+}
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = &'a Value;
+
+    fn next(&mut self) -> Option<&'a Value> {
+        for value in &mut self.nodes {
+            match *value {
+                Value::Int(_) | Value::ByteString(_) => return Some(value),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Decodes a single bencode value into a `Value`, tolerating dicts whose keys are out of order or
+/// repeated, as written by some buggy encoders.
+///
+/// Keys end up sorted regardless of the order they appeared in, so the result re-encodes
+/// canonically. When a key is repeated, the last occurrence wins.
+pub fn from_slice_lenient(input: &[u8]) -> Result<Value> {
+    let mut events = EventReader::new(SliceRead::new(input));
+    let first = try!(next_event(&mut events));
+    build(&mut events, first)
+}
+
+fn next_event<R>(events: &mut EventReader<R>) -> Result<Event>
+    where R: Read
+{
+    // The reader reports unexpected EOF itself, and we never ask for more than one value
+    events.next().expect("event stream ended early")
+}
+
+fn build<R>(events: &mut EventReader<R>, event: Event) -> Result<Value>
+    where R: Read
+{
+    match event {
+        Event::Integer(i) => Ok(Value::Int(i)),
+        Event::ByteString(bytes) => Ok(Value::ByteString(bytes)),
+        Event::ListStart => {
+            let mut list = vec![];
+            loop {
+                match try!(next_event(events)) {
+                    Event::ListEnd => return Ok(Value::List(list)),
+                    event => list.push(try!(build(events, event))),
+                }
+            }
+        }
+        Event::DictStart => {
+            let mut dict = BTreeMap::new();
+            loop {
+                match try!(next_event(events)) {
+                    Event::DictEnd => return Ok(Value::Dict(dict)),
+                    Event::ByteString(key) => {
+                        let event = try!(next_event(events));
+                        dict.insert(key, try!(build(events, event)));
+                    }
+                    _ => unreachable!("EventReader only yields byte string keys"),
+                }
+            }
+        }
+        Event::ListEnd | Event::DictEnd => unreachable!("EventReader only yields balanced ends"),
+    }
+}
+
+fn write_pretty(out: &mut String, value: &Value, opts: &PrettyOpts, level: usize) {
+    match *value {
+        Value::Int(n) => write!(out, "{}", n).unwrap(),
+        Value::ByteString(ref bytes) => write_pretty_bytes(out, bytes, opts),
+        Value::List(ref list) if list.is_empty() => out.push_str("[]"),
+        Value::List(ref list) => {
+            out.push_str("[\n");
+            for (i, item) in list.iter().enumerate() {
+                push_indent(out, opts, level + 1);
+                write_pretty(out, item, opts, level + 1);
+                out.push_str(if i + 1 < list.len() { ",\n" } else { "\n" });
+            }
+            push_indent(out, opts, level);
+            out.push(']');
+        }
+        Value::Dict(ref dict) if dict.is_empty() => out.push_str("{}"),
+        Value::Dict(ref dict) => {
+            out.push_str("{\n");
+            for (i, (key, item)) in dict.iter().enumerate() {
+                push_indent(out, opts, level + 1);
+                write_pretty_bytes(out, key, opts);
+                out.push_str(": ");
+                write_pretty(out, item, opts, level + 1);
+                out.push_str(if i + 1 < dict.len() { ",\n" } else { "\n" });
+            }
+            push_indent(out, opts, level);
+            out.push('}');
+        }
+    }
+}
+
+fn push_indent(out: &mut String, opts: &PrettyOpts, level: usize) {
+    for _ in 0..opts.indent * level {
+        out.push(' ');
+    }
+}
+
+fn write_pretty_bytes(out: &mut String, bytes: &[u8], opts: &PrettyOpts) {
+    let shown = match opts.max_string_len {
+        Some(max) if bytes.len() > max => max,
+        _ => bytes.len(),
+    };
+    match str::from_utf8(bytes) {
+        Ok(s) => {
+            // Back up to a character boundary so the preview is still valid UTF-8
+            let mut end = shown;
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            write!(out, "{:?}", &s[..end]).unwrap();
+        }
+        Err(_) => {
+            let preview = &bytes[..shown];
+            match opts.binary {
+                BinaryRendering::Hex => {
+                    out.push_str("<hex ");
+                    for b in preview {
+                        write!(out, "{:02x}", b).unwrap();
+                    }
+                    out.push('>');
+                }
+                BinaryRendering::Base64 => {
+                    out.push_str("<base64 ");
+                    push_base64(out, preview);
+                    out.push('>');
+                }
+                BinaryRendering::Escaped => {
+                    out.push_str("b\"");
+                    for &b in preview {
+                        match b {
+                            b'"' => out.push_str("\\\""),
+                            b'\\' => out.push_str("\\\\"),
+                            b'\n' => out.push_str("\\n"),
+                            b'\r' => out.push_str("\\r"),
+                            b'\t' => out.push_str("\\t"),
+                            0x20...0x7e => out.push(b as char),
+                            _ => write!(out, "\\x{:02x}", b).unwrap(),
+                        }
+                    }
+                    out.push('"');
+                }
+            }
+        }
+    }
+    if shown < bytes.len() {
+        write!(out, "... ({} bytes)", bytes.len()).unwrap();
+    }
+}
+
+fn push_base64(out: &mut String, bytes: &[u8]) {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+}
+
+fn string_len(bytes: &[u8]) -> usize {
+    digit_count(bytes.len() as u64) + 1 + bytes.len()
+}
+
+fn digit_count(mut n: u64) -> usize {
+    let mut count = 1;
+    while n >= 10 {
+        n /= 10;
+        count += 1;
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_serialized_len_int() {
+        assert_eq!(Value::Int(0).serialized_len(), "i0e".len());
+        assert_eq!(Value::Int(42).serialized_len(), "i42e".len());
+        assert_eq!(Value::Int(-42).serialized_len(), "i-42e".len());
+        assert_eq!(Value::Int(i64::max_value()).serialized_len(),
+                   "i9223372036854775807e".len());
+        assert_eq!(Value::Int(i64::min_value()).serialized_len(),
+                   "i-9223372036854775808e".len());
+    }
+
+    #[test]
+    fn test_serialized_len_byte_string() {
+        assert_eq!(Value::ByteString(vec![]).serialized_len(), "0:".len());
+        assert_eq!(Value::ByteString(b"spam".to_vec()).serialized_len(),
+                   "4:spam".len());
+        assert_eq!(Value::ByteString(vec![0xff; 10]).serialized_len(), 13);
+    }
+
+    #[test]
+    fn test_serialized_len_nested() {
+        use ser::to_vec;
+
+        let mut inner = BTreeMap::new();
+        inner.insert(b"length".to_vec(), Value::Int(1024));
+        inner.insert(b"path".to_vec(),
+                     Value::List(vec![Value::ByteString(b"dir".to_vec()),
+                                      Value::ByteString(b"file.txt".to_vec())]));
+        let mut outer = BTreeMap::new();
+        outer.insert(b"files".to_vec(), Value::List(vec![Value::Dict(inner)]));
+        outer.insert(b"name".to_vec(), Value::ByteString(b"example".to_vec()));
+        let value = Value::Dict(outer);
+
+        assert_eq!(value.serialized_len(), to_vec(&value).unwrap().len());
+    }
+
+    fn nested() -> Value {
+        // d1:ai1e1:bli2eli3ee4:spame1:cde1:dlee
+        let mut dict = BTreeMap::new();
+        dict.insert(b"a".to_vec(), Value::Int(1));
+        dict.insert(b"b".to_vec(),
+                    Value::List(vec![Value::Int(2),
+                                     Value::List(vec![Value::Int(3)]),
+                                     Value::ByteString(b"spam".to_vec())]));
+        dict.insert(b"c".to_vec(), Value::Dict(BTreeMap::new()));
+        dict.insert(b"d".to_vec(), Value::List(vec![]));
+        Value::Dict(dict)
+    }
+
+    #[test]
+    fn test_iter_all() {
+        let value = nested();
+        let nodes: Vec<&Value> = value.iter_all().collect();
+        assert_eq!(nodes.len(), 9);
+        assert_eq!(nodes[0], &value);
+        assert_eq!(nodes[1], &Value::Int(1));
+        assert_eq!(nodes[3], &Value::Int(2));
+        assert_eq!(nodes[4], &Value::List(vec![Value::Int(3)]));
+        assert_eq!(nodes[5], &Value::Int(3));
+        assert_eq!(nodes[6], &Value::ByteString(b"spam".to_vec()));
+        assert_eq!(nodes[7], &Value::Dict(BTreeMap::new()));
+        assert_eq!(nodes[8], &Value::List(vec![]));
+    }
+
+    #[test]
+    fn test_leaves() {
+        let value = nested();
+        let leaves: Vec<&Value> = value.leaves().collect();
+        assert_eq!(leaves,
+                   vec![&Value::Int(1),
+                        &Value::Int(2),
+                        &Value::Int(3),
+                        &Value::ByteString(b"spam".to_vec())]);
+
+        let sum: i64 = value.leaves()
+            .filter_map(|v| match *v {
+                Value::Int(n) => Some(n),
+                _ => None,
+            })
+            .sum();
+        assert_eq!(sum, 6);
+    }
+
+    #[test]
+    fn test_leaves_of_scalar() {
+        let value = Value::Int(7);
+        assert_eq!(value.leaves().collect::<Vec<_>>(), vec![&Value::Int(7)]);
+        assert_eq!(Value::List(vec![]).leaves().count(), 0);
+    }
+
+    #[test]
+    fn test_retain() {
+        fn info() -> Value {
+            let mut info = BTreeMap::new();
+            info.insert(b"name".to_vec(), Value::ByteString(b"example".to_vec()));
+            info.insert(b"private".to_vec(), Value::Int(1));
+            Value::Dict(info)
+        }
+
+        let mut torrent = BTreeMap::new();
+        torrent.insert(b"announce".to_vec(),
+                       Value::ByteString(b"http://tracker.example/announce".to_vec()));
+        torrent.insert(b"info".to_vec(), info());
+        let mut value = Value::Dict(torrent);
+
+        value.retain(|key, _| key != b"announce");
+        assert_eq!(value.serialized_len(),
+                   "d4:infod4:name7:example7:privatei1eee".len());
+
+        let mut expected = BTreeMap::new();
+        expected.insert(b"info".to_vec(), info());
+        assert_eq!(value, Value::Dict(expected));
+
+        value.retain(|_, v| match *v {
+            Value::Dict(_) => false,
+            _ => true,
+        });
+        assert_eq!(value, Value::Dict(BTreeMap::new()));
+    }
+
+    #[test]
+    fn test_transform_increments_ints() {
+        let value: Value = from_slice(b"d1:ai1e1:bli2eli3e1:xee1:cd1:di-1eee").unwrap();
+        let value = value.transform(|value| match value {
+            Value::Int(n) => Value::Int(n + 1),
+            value => value,
+        });
+        assert_eq!(value, from_slice::<Value>(b"d1:ai2e1:bli3eli4e1:xee1:cd1:di0eee").unwrap());
+        assert_eq!(Value::Int(7).transform(|_| Value::from("x")), Value::from("x"));
+    }
+
+    #[test]
+    fn test_transform_is_post_order() {
+        let value: Value = from_slice(b"ll1:aeli1eee").unwrap();
+        let mut visited = vec![];
+        let value = value.transform(|value| {
+            visited.push(value.kind());
+            match value {
+                // Lists whose elements were all pruned are pruned in turn
+                Value::ByteString(_) => Value::List(vec![]),
+                Value::List(ref list) if list.iter().all(|v| v.is_empty()) => Value::List(vec![]),
+                value => value,
+            }
+        });
+        assert_eq!(visited,
+                   vec![Kind::ByteString, Kind::List, Kind::Int, Kind::List, Kind::List]);
+        assert_eq!(value, from_slice::<Value>(b"lleli1eee").unwrap());
+    }
+
+    #[test]
+    fn test_retain_non_dict() {
+        let mut value = Value::List(vec![Value::Int(1)]);
+        value.retain(|_, _| false);
+        assert_eq!(value, Value::List(vec![Value::Int(1)]));
+    }
+
+    #[test]
+    fn test_lenient_unsorted_keys() {
+        let value = from_slice_lenient(b"d1:bi2e1:ai1ee").unwrap();
+        let mut expected = BTreeMap::new();
+        expected.insert(b"a".to_vec(), Value::Int(1));
+        expected.insert(b"b".to_vec(), Value::Int(2));
+        assert_eq!(value, Value::Dict(expected));
+    }
+
+    #[test]
+    fn test_lenient_duplicate_keys() {
+        let value = from_slice_lenient(b"d1:ai1e1:bli1ee1:ai3ee").unwrap();
+        let mut expected = BTreeMap::new();
+        expected.insert(b"a".to_vec(), Value::Int(3));
+        expected.insert(b"b".to_vec(), Value::List(vec![Value::Int(1)]));
+        assert_eq!(value, Value::Dict(expected));
+    }
+
+    #[test]
+    fn test_lenient_nested() {
+        let value = from_slice_lenient(b"d1:bi1e1:ad1:yi2e1:xi3eee").unwrap();
+        assert_eq!(value.serialized_len(), "d1:ad1:xi3e1:yi2ee1:bi1ee".len());
+        assert_eq!(from_slice_lenient(b"4:spam").unwrap(),
+                   Value::ByteString(b"spam".to_vec()));
+    }
+
+    #[test]
+    fn test_lenient_still_rejects_malformed_input() {
+        assert!(from_slice_lenient(b"").is_err());
+        assert!(from_slice_lenient(b"d1:ai1e").is_err());
+        assert!(from_slice_lenient(b"di1ei2ee").is_err());
+        assert!(from_slice_lenient(b"i1ei2e").is_err());
+    }
+
+    #[test]
+    fn test_default() {
+        let value = Value::default();
+        assert_eq!(value, Value::Dict(BTreeMap::new()));
+        assert_eq!(value.serialized_len(), "de".len());
+
+        let mut list = Value::List(vec![Value::Int(1)]);
+        assert_eq!(::std::mem::take(&mut list), Value::List(vec![Value::Int(1)]));
+        assert_eq!(list, Value::Dict(BTreeMap::new()));
+    }
+
+    #[test]
+    fn test_len() {
+        assert_eq!(Value::List(vec![]).len(), Some(0));
+        assert!(Value::List(vec![]).is_empty());
+        assert_eq!(Value::Dict(BTreeMap::new()).len(), Some(0));
+        assert!(Value::Dict(BTreeMap::new()).is_empty());
+
+        let value = nested();
+        assert_eq!(value.len(), Some(4));
+        assert!(!value.is_empty());
+        assert_eq!(Value::List(vec![Value::Int(1), Value::Int(2)]).len(), Some(2));
+
+        assert_eq!(Value::ByteString(b"spam".to_vec()).len(), Some(4));
+        assert!(Value::ByteString(vec![]).is_empty());
+        assert_eq!(Value::Int(0).len(), None);
+        assert!(!Value::Int(0).is_empty());
+    }
+
+    #[test]
+    fn test_limited_within_limits() {
+        let input = b"d1:ai1e1:bli2eli3ee4:spame1:cde1:dlee";
+        // Four keys and nine values, nested three deep, with no string longer than four bytes
+        assert_eq!(Value::from_slice_limited(input, 3, 13, 4).unwrap(), nested());
+    }
+
+    #[test]
+    fn test_limited_does_not_check_keys() {
+        let value = Value::from_slice_limited(b"d1:bi1e1:ai2e1:ai3ee", 1, 100, 100).unwrap();
+        assert_eq!(value, from_slice::<Value>(b"d1:ai3e1:bi1ee").unwrap());
+    }
+
+    #[test]
+    fn test_limited_depth() {
+        match Value::from_slice_limited(b"llli1eeee", 2, 100, 100) {
+            Err(Error::Syntax(ErrorCode::DepthLimitExceeded(2), 3)) => {}
+            other => panic!("Expected a depth limit error, got {:?}", other),
+        }
+        assert!(Value::from_slice_limited(b"llee", 2, 100, 100).is_ok());
+        // Depth goes back down as containers close
+        assert!(Value::from_slice_limited(b"llelelee", 2, 100, 100).is_ok());
+        assert!(Value::from_slice_limited(b"i1e", 0, 100, 100).is_ok());
+    }
+
+    #[test]
+    fn test_limited_nodes() {
+        match Value::from_slice_limited(b"d1:ai1e1:bi2ee", 100, 4, 100) {
+            Err(Error::Syntax(ErrorCode::NodeLimitExceeded(4), 11)) => {}
+            other => panic!("Expected a node limit error, got {:?}", other),
+        }
+        assert!(Value::from_slice_limited(b"d1:ai1e1:bi2ee", 100, 5, 100).is_ok());
+    }
+
+    #[test]
+    fn test_limited_string_length() {
+        match Value::from_slice_limited(b"l3:abc4:spame", 100, 100, 3) {
+            Err(Error::Syntax(ErrorCode::StringTooLong(4), 8)) => {}
+            other => panic!("Expected a string length error, got {:?}", other),
+        }
+        // The declared length is rejected before any attempt to read or allocate it
+        match Value::from_slice_limited(b"99999999999:", 100, 100, 1024) {
+            Err(Error::Syntax(ErrorCode::StringTooLong(99999999999), 12)) => {}
+            other => panic!("Expected a string length error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_eq_str() {
+        let value = Value::ByteString(b"http://tracker".to_vec());
+        assert!(value == "http://tracker");
+        assert!(value == *"http://tracker");
+        assert!(value != "http://other");
+        assert!(Value::Int(1) != "1");
+        assert!(Value::List(vec![Value::ByteString(b"a".to_vec())]) != "a");
+        assert!(Value::Dict(BTreeMap::new()) != "de");
+    }
+
+    #[test]
+    fn test_eq_bytes() {
+        let value = Value::ByteString(vec![0xff, 0x00]);
+        assert!(value == [0xff, 0x00][..]);
+        assert!(value != [0xff][..]);
+        assert!(Value::Int(0) != b"0"[..]);
+        assert!(Value::List(vec![Value::ByteString(vec![0xff, 0x00])]) != [0xff, 0x00][..]);
+    }
+
+    #[test]
+    fn test_eq_i64() {
+        assert!(Value::Int(42) == 42);
+        assert!(Value::Int(-1) != 1);
+        assert!(Value::ByteString(b"42".to_vec()) != 42);
+        assert!(Value::List(vec![Value::Int(42)]) != 42);
+    }
+
+    fn torrent_like() -> Value {
+        let mut info = BTreeMap::new();
+        info.insert(b"length".to_vec(), Value::Int(7));
+        info.insert(b"pieces".to_vec(), Value::ByteString(vec![0xff, 0x00, 0x10, b'a']));
+        let mut dict = BTreeMap::new();
+        dict.insert(b"announce".to_vec(), Value::ByteString(b"http://a.b/c".to_vec()));
+        dict.insert(b"info".to_vec(), Value::Dict(info));
+        dict.insert(b"urls".to_vec(), Value::List(vec![]));
+        Value::Dict(dict)
+    }
+
+    #[test]
+    fn test_pretty_hex() {
+        assert_eq!(torrent_like().pretty(PrettyOpts::default()),
+                   "{\n  \"announce\": \"http://a.b/c\",\n  \"info\": {\n    \"length\": 7,\n    \
+                    \"pieces\": <hex ff001061>\n  },\n  \"urls\": []\n}");
+    }
+
+    #[test]
+    fn test_pretty_base64() {
+        let opts = PrettyOpts {
+            indent: 0,
+            binary: BinaryRendering::Base64,
+            ..PrettyOpts::default()
+        };
+        assert_eq!(torrent_like().pretty(opts),
+                   "{\n\"announce\": \"http://a.b/c\",\n\"info\": {\n\"length\": 7,\n\
+                    \"pieces\": <base64 /wAQYQ==>\n},\n\"urls\": []\n}");
+        for &(bytes, encoded) in &[(&b"\xff"[..], "<base64 /w==>"),
+                                   (&b"\xff\x00"[..], "<base64 /wA=>"),
+                                   (&b"\xff\x00\x01"[..], "<base64 /wAB>")] {
+            assert_eq!(Value::ByteString(bytes.to_vec()).pretty(opts), encoded);
+        }
+    }
+
+    #[test]
+    fn test_pretty_escaped() {
+        let opts = PrettyOpts { binary: BinaryRendering::Escaped, ..PrettyOpts::default() };
+        let value = Value::List(vec![Value::ByteString(b"a\"\\\n\xff\x00".to_vec()),
+                                     Value::Int(-1)]);
+        assert_eq!(value.pretty(opts), "[\n  b\"a\\\"\\\\\\n\\xff\\x00\",\n  -1\n]");
+    }
+
+    #[test]
+    fn test_pretty_truncates_strings() {
+        let opts = PrettyOpts { max_string_len: Some(3), ..PrettyOpts::default() };
+        assert_eq!(Value::ByteString(b"spam".to_vec()).pretty(opts),
+                   "\"spa\"... (4 bytes)");
+        assert_eq!(Value::ByteString(b"egg".to_vec()).pretty(opts), "\"egg\"");
+        assert_eq!(Value::ByteString(vec![0xff; 20]).pretty(opts),
+                   "<hex ffffff>... (20 bytes)");
+        // Never splits a multi-byte character
+        assert_eq!(Value::ByteString("aé".as_bytes().to_vec()).pretty(PrettyOpts {
+                       max_string_len: Some(2),
+                       ..opts
+                   }),
+                   "\"a\"... (3 bytes)");
+    }
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(Value::Int(1).kind(), Kind::Int);
+        assert_eq!(Value::ByteString(vec![]).kind(), Kind::ByteString);
+        assert_eq!(Value::List(vec![]).kind(), Kind::List);
+        assert_eq!(Value::default().kind(), Kind::Dict);
+    }
+
+    #[test]
+    fn test_require_present() {
+        let value = from_slice_lenient(b"d8:announce3:url4:infod4:name1:aee").unwrap();
+        value.require(&[("announce", Kind::ByteString), ("info", Kind::Dict)]).unwrap();
+        value.require(&[]).unwrap();
+    }
+
+    #[test]
+    fn test_require_missing_and_mistyped() {
+        let value = from_slice_lenient(b"d8:announcei1e4:infolee").unwrap();
+        let keys = [("announce", Kind::ByteString),
+                    ("comment", Kind::ByteString),
+                    ("info", Kind::Dict),
+                    ("nodes", Kind::List)];
+        let err = value.require(&keys).unwrap_err();
+        match err {
+            Error::Syntax(ErrorCode::RequiredKeys { ref missing, ref mistyped }, _) => {
+                assert_eq!(*missing, vec!["comment".to_string(), "nodes".to_string()]);
+                assert_eq!(*mistyped,
+                           vec![("announce".to_string(), Kind::ByteString, Kind::Int),
+                                ("info".to_string(), Kind::Dict, Kind::List)]);
+            }
+            ref other => panic!("Expected a required keys error, got {:?}", other),
+        }
+        assert_eq!(err.to_string(),
+                   "At position 0: Missing keys: comment, nodes; wrong type for keys: announce \
+                    (expected byte string, found integer), info (expected dictionary, found \
+                    list)");
+    }
+
+    #[test]
+    fn test_require_not_a_dict() {
+        let err = Value::List(vec![]).require(&[("info", Kind::Dict)]).unwrap_err();
+        assert_eq!(err.to_string(), "At position 0: Missing keys: info");
+    }
+
+    #[test]
+    fn test_expect_dict_len() {
+        let value = from_slice_lenient(b"d1:ai1e1:bi2ee").unwrap();
+        value.expect_dict_len(2).unwrap();
+        Value::default().expect_dict_len(0).unwrap();
+
+        let err = value.expect_dict_len(3).unwrap_err();
+        assert_eq!(err.code(),
+                   Some(&ErrorCode::DictLength {
+                       expected: 3,
+                       found: 2,
+                   }));
+        assert_eq!(err.to_string(),
+                   "At position 0: Expected a dictionary of 3 entries, found 2");
+        assert!(value.expect_dict_len(1).is_err());
+
+        for &(ref value, kind) in &[(Value::Int(2), Kind::Int),
+                                    (Value::ByteString(b"ab".to_vec()), Kind::ByteString),
+                                    (Value::List(vec![Value::Int(1), Value::Int(2)]), Kind::List)] {
+            let err = value.expect_dict_len(2).unwrap_err();
+            assert_eq!(err.code(),
+                       Some(&ErrorCode::UnexpectedKind {
+                           expected: Kind::Dict,
+                           found: kind,
+                       }));
+        }
+        assert_eq!(Value::List(vec![]).expect_dict_len(0).unwrap_err().to_string(),
+                   "At position 0: Expected dictionary, found list");
+    }
+
+    #[test]
+    fn test_iter_list() {
+        let value = from_slice_lenient(b"li1e4:spamlee").unwrap();
+        let mut elements = vec![];
+        for element in value.iter_list().unwrap() {
+            elements.push(element.kind());
+        }
+        assert_eq!(elements, vec![Kind::Int, Kind::ByteString, Kind::List]);
+
+        assert_eq!(Value::List(vec![]).iter_list().unwrap().count(), 0);
+        assert!(Value::Int(1).iter_list().is_none());
+        assert!(Value::default().iter_list().is_none());
+    }
+
+    #[test]
+    fn test_iter_dict() {
+        let value = from_slice_lenient(b"d4:spami1e3:cow3:mooe").unwrap();
+        let mut entries = vec![];
+        for (key, value) in value.iter_dict().unwrap() {
+            entries.push((key.to_vec(), value.clone()));
+        }
+        assert_eq!(entries,
+                   vec![(b"cow".to_vec(), Value::ByteString(b"moo".to_vec())),
+                        (b"spam".to_vec(), Value::Int(1))]);
+
+        assert_eq!(Value::default().iter_dict().unwrap().count(), 0);
+        assert!(Value::ByteString(vec![]).iter_dict().is_none());
+        assert!(Value::List(vec![]).iter_dict().is_none());
+    }
+
+    #[test]
+    fn test_accessors() {
+        let torrent = from_slice_lenient(b"d8:announce3:url4:infod6:lengthi12e4:name5:a.txt6:pieces\
+                                           2:\xff\x00e4:listli1ei2eee")
+            .unwrap();
+        let info = torrent.get("info").unwrap();
+        assert_eq!(info.get("name").and_then(Value::as_str), Some("a.txt"));
+        assert_eq!(info.get(b"name").and_then(Value::as_bytes), Some(&b"a.txt"[..]));
+        assert_eq!(info.get("length").and_then(Value::as_i64), Some(12));
+        assert_eq!(info.get("pieces").and_then(Value::as_bytes),
+                   Some(&b"\xff\x00"[..]));
+        assert_eq!(info.get("pieces").and_then(Value::as_str), None);
+        assert_eq!(info.as_dict().map(BTreeMap::len), Some(3));
+        assert_eq!(torrent.get("list").and_then(Value::as_list),
+                   Some(&vec![Value::Int(1), Value::Int(2)]));
+
+        // Missing keys and the wrong kinds of value give `None`
+        assert!(torrent.get("comment").is_none());
+        assert!(info.get("length").and_then(|length| length.get("x")).is_none());
+        assert!(torrent.get("announce").and_then(Value::as_list).is_none());
+        assert!(torrent.get("list").and_then(Value::as_dict).is_none());
+        assert!(torrent.get("list").and_then(Value::as_bytes).is_none());
+        assert!(torrent.as_str().is_none());
+    }
+
+    fn sample_torrent() -> Value {
+        let mut info = BTreeMap::new();
+        info.insert(b"name".to_vec(), Value::ByteString(b"example".to_vec()));
+        info.insert(b"piece length".to_vec(), Value::Int(16384));
+        let mut torrent = BTreeMap::new();
+        torrent.insert(b"announce".to_vec(),
+                       Value::ByteString(b"http://tracker/announce".to_vec()));
+        torrent.insert(b"info".to_vec(), Value::Dict(info));
+        Value::Dict(torrent)
+    }
+
+    #[test]
+    fn test_get_path() {
+        let torrent = sample_torrent();
+        assert_eq!(torrent.get_path(&[b"info", b"piece length"]),
+                   Some(Value::Int(16384)));
+        assert_eq!(torrent.get_path(&[b"announce"]),
+                   Some(Value::ByteString(b"http://tracker/announce".to_vec())));
+        assert_eq!(torrent.get_path(&[]), Some(sample_torrent()));
+    }
+
+    #[test]
+    fn test_get_path_missing() {
+        let torrent = sample_torrent();
+        assert_eq!(torrent.get_path(&[b"info", b"length"]), None);
+        assert_eq!(torrent.get_path(&[b"comment"]), None);
+        // `name` is a byte string, so there is nothing beneath it
+        assert_eq!(torrent.get_path(&[b"info", b"name", b"x"]), None);
+    }
+
+    #[test]
+    fn test_get_path_across_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let torrent = Arc::new(sample_torrent());
+        let shared = torrent.clone();
+        let name = thread::spawn(move || shared.get_path(&[b"info", b"name"]))
+            .join()
+            .unwrap();
+        assert_eq!(name, Some(Value::ByteString(b"example".to_vec())));
+    }
+
+    #[test]
+    fn test_clone_is_independent() {
+        let original = sample_torrent();
+        let mut copy = original.clone();
+        assert_eq!(copy, original);
+
+        if let Value::Dict(ref mut torrent) = copy {
+            torrent.remove(&b"announce"[..]);
+            if let Some(&mut Value::Dict(ref mut info)) = torrent.get_mut(&b"info"[..]) {
+                info.insert(b"piece length".to_vec(), Value::Int(32768));
+            }
+        }
+        assert_eq!(copy.get_path(&[b"info", b"piece length"]),
+                   Some(Value::Int(32768)));
+        assert_eq!(copy.get_path(&[b"announce"]), None);
+        assert_eq!(original, sample_torrent());
+    }
+
+    #[test]
+    fn test_semantic_eq() {
+        let mut a = BTreeMap::new();
+        a.insert(b"name".to_vec(), Value::ByteString(b"example".to_vec()));
+        a.insert(b"length".to_vec(), Value::Int(1024));
+        let mut b = BTreeMap::new();
+        b.insert(b"length".to_vec(), Value::Int(1024));
+        b.insert(b"name".to_vec(), Value::ByteString(b"example".to_vec()));
+        assert!(Value::Dict(a.clone()).semantic_eq(&Value::Dict(b.clone())));
+
+        // Nested within lists and dicts, built in different orders
+        let mut outer_a = BTreeMap::new();
+        outer_a.insert(b"files".to_vec(), Value::List(vec![Value::Dict(a.clone())]));
+        outer_a.insert(b"info".to_vec(), Value::Dict(a.clone()));
+        let mut outer_b = BTreeMap::new();
+        outer_b.insert(b"info".to_vec(), Value::Dict(b.clone()));
+        outer_b.insert(b"files".to_vec(), Value::List(vec![Value::Dict(b.clone())]));
+        assert!(Value::Dict(outer_a.clone()).semantic_eq(&Value::Dict(outer_b.clone())));
+
+        b.insert(b"length".to_vec(), Value::Int(2048));
+        outer_b.insert(b"info".to_vec(), Value::Dict(b));
+        assert!(!Value::Dict(outer_a).semantic_eq(&Value::Dict(outer_b)));
+    }
+
+    #[test]
+    fn test_semantic_eq_mismatches() {
+        let one = Value::List(vec![Value::Int(1)]);
+        assert!(!one.semantic_eq(&Value::List(vec![])));
+        assert!(!one.semantic_eq(&Value::List(vec![Value::Int(1), Value::Int(1)])));
+        assert!(!Value::Int(1).semantic_eq(&Value::ByteString(b"1".to_vec())));
+        assert!(!Value::List(vec![]).semantic_eq(&Value::Dict(BTreeMap::new())));
+
+        let mut a = BTreeMap::new();
+        a.insert(b"a".to_vec(), Value::Int(1));
+        let mut b = BTreeMap::new();
+        b.insert(b"b".to_vec(), Value::Int(1));
+        assert!(!Value::Dict(a).semantic_eq(&Value::Dict(b)));
+    }
+
+    #[test]
+    fn test_deserialize() {
+        use de::from_slice;
+
+        assert_eq!(from_slice::<Value>(b"i-42e").unwrap(), Value::Int(-42));
+        assert_eq!(from_slice::<Value>(b"2:\xff\x00").unwrap(),
+                   Value::ByteString(vec![0xff, 0x00]));
+
+        let value: Value = from_slice(b"d8:announce23:http://tracker/announce4:infod4:name7:exam\
+                                        ple12:piece lengthi16384eee")
+            .unwrap();
+        assert_eq!(value, sample_torrent());
+
+        let value: Value = from_slice(b"ll1:ai1eed1:xlee0:lee").unwrap();
+        let mut dict = BTreeMap::new();
+        dict.insert(b"x".to_vec(), Value::List(vec![]));
+        assert_eq!(value,
+                   Value::List(vec![Value::List(vec![Value::ByteString(b"a".to_vec()),
+                                                     Value::Int(1)]),
+                                    Value::Dict(dict),
+                                    Value::ByteString(vec![]),
+                                    Value::List(vec![])]));
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        use de::from_slice;
+
+        assert!(from_slice::<Value>(b"i9223372036854775808e").is_err());
+        assert!(from_slice::<Value>(b"di1ei2ee").is_err());
+        assert!(from_slice::<Value>(b"l4:spam").is_err());
+        assert!(from_slice::<Value>(b"i1ei2e").is_err());
+    }
+
+    #[test]
+    fn test_serialize() {
+        use de::from_slice;
+        use ser::to_vec;
+
+        assert_eq!(to_vec(&Value::Int(-42)).unwrap(), b"i-42e");
+        assert_eq!(to_vec(&Value::ByteString(vec![0xff, 0x00])).unwrap(), b"2:\xff\x00");
+        assert_eq!(to_vec(&Value::List(vec![])).unwrap(), b"le");
+        assert_eq!(to_vec(&Value::default()).unwrap(), b"de");
+
+        let input: &[u8] = b"d8:announce23:http://tracker/announce4:infod5:filesld6:lengthi1e4:p\
+                             athl1:aeee4:name7:example12:piece lengthi16384e6:pieces2:\xff\x00ee";
+        let value: Value = from_slice(input).unwrap();
+        assert_eq!(to_vec(&value).unwrap(), input);
+    }
+
+    #[test]
+    fn test_serialize_after_changes() {
+        use de::from_slice;
+        use ser::to_vec;
+
+        let mut value: Value = from_slice(b"d1:ai1e1:ci3ee").unwrap();
+        if let Value::Dict(ref mut dict) = value {
+            dict.insert(b"b".to_vec(), Value::List(vec![Value::Int(2)]));
+        }
+        assert_eq!(to_vec(&value).unwrap(), b"d1:ai1e1:bli2ee1:ci3ee");
+    }
+
+    #[test]
+    fn test_from_conversions() {
+        use ser::to_vec;
+
+        assert_eq!(Value::from(42), Value::Int(42));
+        assert_eq!(Value::from("spam"), Value::ByteString(b"spam".to_vec()));
+        assert_eq!(Value::from("spam".to_string()), Value::from("spam"));
+
+        let list = Value::from(vec![Value::from(-1), Value::from("a")]);
+        assert_eq!(to_vec(&list).unwrap(), b"li-1e1:ae");
+
+        let mut dict = BTreeMap::new();
+        dict.insert(b"x".to_vec(), Value::from(1));
+        assert_eq!(to_vec(&Value::from(dict)).unwrap(), b"d1:xi1ee");
+    }
+
+    #[test]
+    fn test_collect_dict() {
+        use de::from_slice;
+        use ser::to_vec;
+
+        let value: Value = vec![("port", Value::from(6881)),
+                                ("id", Value::from("abc")),
+                                ("peers", Value::from(vec![]))]
+            .into_iter()
+            .collect();
+        let encoded = to_vec(&value).unwrap();
+        assert_eq!(encoded, b"d2:id3:abc5:peersle4:porti6881ee");
+        assert_eq!(from_slice::<Value>(&encoded).unwrap(), value);
+    }
+
+    #[test]
+    fn test_dict_keys_reencode_sorted() {
+        use de::from_slice;
+        use ser::to_vec;
+
+        let value: Value = from_slice(b"d1:bi1e1:ai2ee").unwrap();
+        assert_eq!(to_vec(&value).unwrap(), b"d1:ai2e1:bi1ee");
+        assert_eq!(to_vec(&from_slice_lenient(b"d1:bi1e1:ai2ee").unwrap()).unwrap(),
+                   b"d1:ai2e1:bi1ee");
+    }
+
+    #[test]
+    fn test_int_accessors() {
+        let n = Value::Int(1024);
+        assert_eq!(n.as_i64(), Some(1024));
+        assert_eq!(n.as_i32(), Some(1024));
+        assert_eq!(n.as_u64(), Some(1024));
+        assert_eq!(n.as_u32(), Some(1024));
+        assert_eq!(n.as_u8(), None);
+
+        assert_eq!(Value::Int(i64::max_value()).as_u64(), Some(i64::max_value() as u64));
+        assert_eq!(Value::Int(i64::max_value()).as_i32(), None);
+        assert_eq!(Value::Int(1 << 32).as_u32(), None);
+        assert_eq!(Value::Int(u32::max_value() as i64).as_u32(), Some(u32::max_value()));
+        assert_eq!(Value::Int(i32::min_value() as i64).as_i32(), Some(i32::min_value()));
+        assert_eq!(Value::Int(i32::min_value() as i64 - 1).as_i32(), None);
+    }
+
+    #[test]
+    fn test_int_accessors_negative_into_unsigned() {
+        let n = Value::Int(-1);
+        assert_eq!(n.as_i64(), Some(-1));
+        assert_eq!(n.as_i32(), Some(-1));
+        assert_eq!(n.as_u64(), None);
+        assert_eq!(n.as_u32(), None);
+        assert_eq!(n.as_u8(), None);
+        assert_eq!(Value::ByteString(b"1".to_vec()).as_i64(), None);
+        assert_eq!(Value::List(vec![]).as_u32(), None);
+    }
+
+    #[test]
+    fn test_lazy_value() {
+        let input = b"d8:announce3:url4:infod6:lengthi7e4:name4:filee7:privatei1ee";
+        let entries = LazyValue::new(input).entries().unwrap();
+        assert_eq!(entries.keys().collect::<Vec<_>>(),
+                   vec![&b"announce"[..], b"info", b"private"]);
+        assert_eq!(entries[&b"info"[..]].as_bytes(), b"d6:lengthi7e4:name4:filee");
+        assert_eq!(entries[&b"announce"[..]].force().unwrap(),
+                   Value::ByteString(b"url".to_vec()));
+
+        let info = entries[&b"info"[..]].force().unwrap();
+        assert_eq!(info.get_path(&[b"length"]), Some(Value::Int(7)));
+        assert_eq!(LazyValue::new(input).force().unwrap().get_path(&[b"info"]), Some(info));
+    }
+
+    #[test]
+    fn test_lazy_value_defers_decoding() {
+        // `Value::Int` can't hold this integer, but that only comes to light once `info` is forced
+        let input = b"d4:infod6:lengthi18446744073709551615ee4:name4:filee";
+        let entries = LazyValue::new(input).entries().unwrap();
+        assert_eq!(entries[&b"name"[..]].force().unwrap(),
+                   Value::ByteString(b"file".to_vec()));
+        assert!(entries[&b"info"[..]].force().is_err());
+        assert!(LazyValue::new(input).force().is_err());
+    }
+
+    #[test]
+    fn test_lazy_value_errors() {
+        assert!(LazyValue::new(b"li1ee").entries().is_err());
+        assert!(LazyValue::new(b"d1:ai1e").entries().is_err());
+        assert!(LazyValue::new(b"di1ei2ee").entries().is_err());
+        assert!(LazyValue::new(b"d1:ai1eei1e").entries().is_err());
+        assert_eq!(LazyValue::new(b"de").entries().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let a = from_slice_lenient(b"d3:cow3:moo4:spaml1:a1:bee").unwrap();
+        let b = from_slice_lenient(b"d4:spaml1:a1:be3:cow3:mooe").unwrap();
+        assert_eq!(a.content_hash(), b.content_hash());
+
+        let mut dict = BTreeMap::new();
+        dict.insert(b"spam".to_vec(),
+                    Value::List(vec![Value::ByteString(b"a".to_vec()),
+                                     Value::ByteString(b"b".to_vec())]));
+        dict.insert(b"cow".to_vec(), Value::ByteString(b"moo".to_vec()));
+        assert_eq!(Value::Dict(dict).content_hash(), a.content_hash());
+
+        // The hash is of the canonical encoding, so it is fixed for a given value
+        let mut w = HashWriter::new();
+        ::std::io::Write::write_all(&mut w, b"d3:cow3:moo4:spaml1:a1:bee").unwrap();
+        assert_eq!(a.content_hash(), w.finish());
+
+        let c = from_slice_lenient(b"d3:cow3:moo4:spaml1:b1:aee").unwrap();
+        assert!(a.content_hash() != c.content_hash());
+        assert!(Value::Int(1).content_hash() != Value::ByteString(b"1".to_vec()).content_hash());
+    }
+}
@@ -7,6 +7,11 @@ extern crate serde_derive;
 extern crate serde;
 extern crate serde_bencode;
 
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::bytes::ByteBuf;
+
 #[cfg(feature = "serde_derive")]
 include!("serde_types.in.rs");
 
@@ -44,3 +49,260 @@ fn integration_test() {
     let deserialized: Complex = serde_bencode::from_string(serialized).unwrap();
     assert_eq!(deserialized, c);
 }
+
+#[test]
+fn test_unit_variant_round_trip() {
+    let colors = vec![Color::Red, Color::Green, Color::Blue, Color::Red];
+
+    let serialized = serde_bencode::to_string(&colors).unwrap();
+    assert_eq!(serialized, "l3:Red5:Green4:Blue3:Rede");
+
+    let deserialized: Vec<Color> = serde_bencode::from_string(serialized).unwrap();
+    assert_eq!(deserialized, colors);
+}
+
+#[test]
+fn test_unit_variant_keyed_map_round_trip() {
+    use std::collections::HashMap;
+
+    let mut map = HashMap::new();
+    map.insert(Color::Red, 1);
+    map.insert(Color::Green, 2);
+
+    let serialized = serde_bencode::to_string(&map).unwrap();
+    assert_eq!(serialized, "d5:Greeni2e3:Redi1ee");
+
+    let deserialized: HashMap<Color, i32> = serde_bencode::from_string(serialized).unwrap();
+    assert_eq!(deserialized, map);
+}
+
+#[test]
+fn test_duration_as_seconds_round_trip() {
+    let announce = Announce { interval: ::std::time::Duration::from_secs(1800) };
+
+    let serialized = serde_bencode::to_string(&announce).unwrap();
+    assert_eq!(serialized, "d8:intervali1800ee");
+
+    let deserialized: Announce = serde_bencode::from_string(serialized).unwrap();
+    assert_eq!(deserialized, announce);
+
+    let negative: serde_bencode::error::Result<Announce> =
+        serde_bencode::from_string("d8:intervali-1800ee".to_string());
+    assert!(negative.is_err());
+}
+
+#[test]
+fn test_deny_unknown_fields() {
+    let known: StrictPoint = serde_bencode::from_string("d1:xi1e1:yi2ee".to_string()).unwrap();
+    assert_eq!(known, StrictPoint { x: 1, y: 2 });
+
+    let unknown: serde_bencode::error::Result<StrictPoint> =
+        serde_bencode::from_string("d1:xi1e1:yi2e1:zi3ee".to_string());
+    assert!(unknown.is_err());
+
+    // Without the attribute, extra keys are skipped
+    let lenient: Point = serde_bencode::from_string("d1:xi1e1:yi2e1:zi3ee".to_string()).unwrap();
+    assert_eq!(lenient, Point { x: 1, y: 2 });
+}
+
+#[test]
+fn test_struct_variant_renamed_fields() {
+    let ping = Query::Ping {
+        transaction: "aa".to_string(),
+        node_id: "abcdefghij0123456789".to_string(),
+    };
+    let serialized = serde_bencode::to_string(&ping).unwrap();
+    assert_eq!(serialized, "d4:Pingd\
+        2:id20:abcdefghij0123456789\
+        1:t2:aa\
+    ee");
+    let deserialized: Query = serde_bencode::from_string(serialized).unwrap();
+    assert_eq!(deserialized, ping);
+
+    let find_node = Query::FindNode {
+        target_id: "mnopqrstuvwxyz123456".to_string(),
+        node_id: "abcdefghij0123456789".to_string(),
+        args: Point { x: 1, y: 2 },
+    };
+    let serialized = serde_bencode::to_string(&find_node).unwrap();
+    assert_eq!(serialized, "d8:FindNoded\
+        1:ad1:xi1e1:yi2ee\
+        2:id20:abcdefghij0123456789\
+        6:target20:mnopqrstuvwxyz123456\
+    ee");
+    let deserialized: Query = serde_bencode::from_string(serialized).unwrap();
+    assert_eq!(deserialized, find_node);
+}
+
+#[test]
+fn test_newtype_variant_bytes_round_trip() {
+    let payload = Payload::Pieces(ByteBuf::from(vec![0xff, 0x00, b'e', 0x80]));
+    let serialized = serde_bencode::to_vec(&payload).unwrap();
+    assert_eq!(serialized, b"d6:Pieces4:\xff\x00e\x80e".to_vec());
+    let deserialized: Payload = serde_bencode::from_slice(&serialized).unwrap();
+    assert_eq!(deserialized, payload);
+}
+
+#[test]
+fn test_raw_info_round_trip() {
+    let input = "d8:announce12:http://a.b/c4:infod6:lengthi7e4:name4:spamee";
+
+    let torrent: RawInfoTorrent = serde_bencode::from_slice(input.as_bytes()).unwrap();
+    assert_eq!(torrent.announce, "http://a.b/c");
+    assert_eq!(torrent.info, b"d6:lengthi7e4:name4:spame".to_vec());
+
+    assert_eq!(serde_bencode::to_string(&torrent).unwrap(), input);
+}
+
+#[test]
+fn test_raw_value_field() {
+    let input = "d8:announce12:http://a.b/c4:infod4:name4:spam6:lengthi7eee";
+    let torrent: RawValueTorrent = serde_bencode::from_str(input).unwrap();
+    assert_eq!(torrent.info.as_bytes(), &input.as_bytes()[32..input.len() - 1]);
+
+    assert_eq!(serde_bencode::to_string(&torrent).unwrap(), input);
+}
+
+/// Stores a value as its `Display` text, like `serde_with::DisplayFromStr`.
+fn serialize_display<T, S>(value: &T, serializer: &mut S) -> Result<(), S::Error>
+    where T: Display,
+          S: serde::Serializer
+{
+    serializer.serialize_str(&value.to_string())
+}
+
+fn deserialize_from_str<T, D>(deserializer: &mut D) -> Result<T, D::Error>
+    where T: FromStr,
+          T::Err: Display,
+          D: serde::Deserializer
+{
+    let s: String = try!(serde::Deserialize::deserialize(deserializer));
+    s.parse().map_err(|err: T::Err| serde::de::Error::invalid_value(&err.to_string()))
+}
+
+#[test]
+fn test_display_from_str_round_trip() {
+    let stats = Stats { downloaded: u64::max_value() };
+
+    let serialized = serde_bencode::to_string(&stats).unwrap();
+    assert_eq!(serialized, "d10:downloaded20:18446744073709551615e");
+
+    let deserialized: Stats = serde_bencode::from_string(serialized).unwrap();
+    assert_eq!(deserialized, stats);
+
+    let result: Result<Stats, _> = serde_bencode::from_string("d10:downloaded3:abce".to_string());
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_optional_binary_field_present() {
+    let entry = FileEntry {
+        name: "a.b".to_string(),
+        opt_hash: Some(ByteBuf::from(vec![0x00, 0x7f, 0x01])),
+    };
+    let input = b"d4:name3:a.b8:opt_hash3:\x00\x7f\x01e";
+
+    assert_eq!(serde_bencode::to_vec(&entry).unwrap(), input.to_vec());
+    let deserialized: FileEntry = serde_bencode::from_slice(input).unwrap();
+    assert_eq!(deserialized, entry);
+}
+
+#[test]
+fn test_optional_binary_field_absent() {
+    let entry = FileEntry {
+        name: "a.b".to_string(),
+        opt_hash: None,
+    };
+    let input = b"d4:name3:a.be";
+
+    assert_eq!(serde_bencode::to_vec(&entry).unwrap(), input.to_vec());
+    let deserialized: FileEntry = serde_bencode::from_slice(input).unwrap();
+    assert_eq!(deserialized, entry);
+}
+
+#[test]
+fn test_omit_empty_round_trip() {
+    let list = TrackerList {
+        name: "a.b".to_string(),
+        announce_list: vec![],
+    };
+    let mut w = Vec::new();
+    {
+        let mut ser = serde_bencode::ser::Serializer::new(&mut w).omit_empty(true);
+        serde::Serialize::serialize(&list, &mut ser).unwrap();
+    }
+    assert_eq!(w, b"d4:name3:a.be".to_vec());
+    let deserialized: TrackerList = serde_bencode::from_slice(&w).unwrap();
+    assert_eq!(deserialized, list);
+}
+
+#[test]
+fn test_tuple_struct_length() {
+    assert_eq!(serde_bencode::to_vec(&Pair(1, 2)).unwrap(), b"li1ei2ee".to_vec());
+    assert_eq!(serde_bencode::from_slice::<Pair>(b"li1ei2ee").unwrap(), Pair(1, 2));
+    for input in &[&b"li1ee"[..], &b"li1ei2ei3ee"[..]] {
+        let err = serde_bencode::from_slice::<Pair>(input).unwrap_err();
+        match err.code() {
+            Some(&serde_bencode::error::ErrorCode::TupleStructLength { name: "Pair",
+                                                                       expected: 2,
+                                                                       .. }) => {}
+            other => panic!("Expected a tuple struct length error, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_empty_struct() {
+    assert_eq!(serde_bencode::to_vec(&Empty {}).unwrap(), b"de".to_vec());
+    assert_eq!(serde_bencode::from_slice::<Empty>(b"de").unwrap(), Empty {});
+    // Unknown keys are skipped as for any other struct
+    assert_eq!(serde_bencode::from_slice::<Empty>(b"d1:ai1ee").unwrap(), Empty {});
+}
+
+/// Decodes `input` both from a slice and from a reader, checking that the two agree on either the
+/// value or the error.
+fn assert_reader_matches_slice<T>(input: &[u8])
+    where T: serde::Deserialize + PartialEq + std::fmt::Debug
+{
+    let from_slice = serde_bencode::from_slice::<T>(input);
+    let from_reader = serde_bencode::from_reader::<_, T>(std::io::Cursor::new(input));
+    match (from_slice, from_reader) {
+        (Ok(a), Ok(b)) => assert_eq!(a, b),
+        (Err(a), Err(b)) => assert_eq!(a.to_string(), b.to_string()),
+        (a, b) => {
+            panic!("from_slice and from_reader disagree on {:?}: {:?} vs {:?}",
+                   String::from_utf8_lossy(input),
+                   a,
+                   b)
+        }
+    }
+}
+
+#[test]
+fn test_from_reader_matches_from_slice() {
+    use std::collections::BTreeMap;
+    use serde_bencode::Value;
+
+    for input in &[&b"i42e"[..], b"i-7e", b"i03e", b"ie", b"i1", b"i1ei2e", b""] {
+        assert_reader_matches_slice::<i64>(input);
+    }
+    for input in &[&b"4:spam"[..], b"0:", b"5:spam", b"4spam", b"2:\xff\x00"] {
+        assert_reader_matches_slice::<String>(input);
+    }
+    for input in &[&b"li1ei2ee"[..], b"le", b"li1e", b"l4:spame"] {
+        assert_reader_matches_slice::<Vec<i64>>(input);
+    }
+    for input in &[&b"d1:ai1e1:bi2ee"[..], b"d1:ai1ee", b"di1ei2ee", b"d1:ai1e"] {
+        assert_reader_matches_slice::<BTreeMap<String, i64>>(input);
+    }
+    for input in &[&b"d1:ald1:xi1eeee"[..], b"lli1eee", b"d1:ae", b"l"] {
+        assert_reader_matches_slice::<Value>(input);
+    }
+    for input in &[&b"d1:ii42e1:s13:Hello, World!1:vld1:xi1e1:yi2eeee"[..],
+                   b"d1:ii42e1:s5:Hello1:vlee",
+                   b"d1:ii42e1:s5:Hello1:vld1:xi1eeee",
+                   b"d1:s5:Hello1:vlee",
+                   b"d1:ii42e1:s5:Hello1:vl"] {
+        assert_reader_matches_slice::<Complex>(input);
+    }
+}
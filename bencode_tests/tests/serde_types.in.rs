@@ -10,3 +10,86 @@ struct Complex {
     i: i32,
     v: Vec<Point>
 }
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+enum Color {
+    Red,
+    Green,
+    Blue,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct Announce {
+    #[serde(serialize_with = "serde_bencode::timestamp::serialize",
+            deserialize_with = "serde_bencode::timestamp::deserialize")]
+    interval: ::std::time::Duration,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct StrictPoint {
+    x: i32,
+    y: i32
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+enum Query {
+    Ping {
+        #[serde(rename = "t")]
+        transaction: String,
+        #[serde(rename = "id")]
+        node_id: String,
+    },
+    FindNode {
+        #[serde(rename = "target")]
+        target_id: String,
+        #[serde(rename = "id")]
+        node_id: String,
+        #[serde(rename = "a")]
+        args: Point,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+enum Payload {
+    Pieces(ByteBuf),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct RawInfoTorrent {
+    announce: String,
+    #[serde(serialize_with = "serde_bencode::raw::serialize",
+            deserialize_with = "serde_bencode::raw::deserialize")]
+    info: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct RawValueTorrent {
+    announce: String,
+    info: serde_bencode::RawValue,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Stats {
+    #[serde(serialize_with = "serialize_display", deserialize_with = "deserialize_from_str")]
+    downloaded: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct FileEntry {
+    name: String,
+    opt_hash: Option<ByteBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct TrackerList {
+    name: String,
+    #[serde(default)]
+    announce_list: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Pair(i32, i32);
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Empty {}